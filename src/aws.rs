@@ -1,31 +1,482 @@
 use conf::DropConfig;
+use credentials;
+use multipart;
+use sigv4;
+use sigv4::Credentials;
+use upload;
+use upload::{Upload, Uploader};
 use util;
 
-use std;
-use std::process::Command;
-use std::path::Path;
+use std::fmt;
+use std::io;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use sha2::Digest;
+use md5::Md5;
+use base64;
+use base64::Engine;
+use ureq;
+use anyhow;
 
-pub fn upload_file_to_s3(config: &DropConfig, file_path: &Path, file_name: &Option<String>) {
+const DEFAULT_REGION: &'static str = "us-east-1";
+const TTL_TAG: &'static str = "drop-ttl";
 
-  if !file_path.exists() {
-    println!("ERROR: File does not exist, nothing to upload to S3! ({:?})", file_path);
-    std::process::exit(1);
+pub struct S3Uploader<'a> {
+  config: &'a DropConfig,
+  client: S3Client,
+}
+
+impl<'a> S3Uploader<'a> {
+  pub fn new(config: &'a DropConfig) -> anyhow::Result<S3Uploader<'a>> {
+    if config.aws_bucket.is_none() {
+      return Err(anyhow!("S3 backend requires an aws bucket to be configured"));
+    }
+    Ok(S3Uploader { config: config, client: S3Client::from_config(config)? })
+  }
+}
+
+impl<'a> Uploader for S3Uploader<'a> {
+  fn name(&self) -> &'static str {
+    "s3"
+  }
+
+  // The stored key can differ from the requested one when an interrupted multipart upload of the
+  // same file is resumed.
+  fn upload(&self, file_path: &Path, key: &str) -> anyhow::Result<Upload> {
+    if !file_path.exists() {
+      return Err(S3Error::MissingFile(file_path.to_path_buf()).into());
+    }
+
+    let mut headers = upload::content_headers(self.config, file_path);
+    if let Some(ttl) = self.config.ttl {
+      headers.push(("x-amz-tagging".to_string(), format!("{}={}", TTL_TAG, ttl_tag_value(ttl_days(ttl)))));
+    }
+    let stored_key =
+      if file_path.metadata()?.len() >= self.config.aws_multipart_threshold {
+        multipart::upload(&self.client, self.config, key, file_path, &headers)?
+      } else {
+        self.client.put_object(key, file_path, &headers)?;
+        key.to_string()
+      };
+
+    Ok(Upload { url: self.produce_url(&stored_key), key: stored_key })
+  }
+
+  fn produce_url(&self, key: &str) -> String {
+    match self.config.aws_presign_expires {
+      Some(expires) => self.client.presigned_get_url(key, expires),
+      None => util::create_drop_url(self.config, key.to_string()),
+    }
+  }
+
+  fn delete(&self, key: &str) -> anyhow::Result<()> {
+    self.client.delete_object(key)?;
+    Ok(())
+  }
+
+  fn exists(&self, key: &str) -> anyhow::Result<bool> {
+    Ok(self.client.head_object(key)?)
+  }
+}
+
+// Installs a bucket lifecycle rule for each ttl, expiring objects tagged with it on upload. Rules
+// drop installed before are replaced, any other rules the bucket has are kept.
+pub fn install_lifecycle_rules(config: &DropConfig, ttls: &[u64]) -> anyhow::Result<Vec<u64>> {
+  let mut days: Vec<u64> = ttls.iter().map(|ttl| ttl_days(*ttl)).collect();
+  days.sort();
+  days.dedup();
+
+  let rules: Vec<(String, String, String, u64)> = days.iter()
+    .map(|d| (format!("{}-{}", TTL_TAG, ttl_tag_value(*d)), TTL_TAG.to_string(), ttl_tag_value(*d), *d))
+    .collect();
+  let client = S3Uploader::new(config)?.client;
+  let existing = client.get_bucket_lifecycle()?;
+  client.put_bucket_lifecycle(&merge_lifecycle_rules(&existing, &rules))?;
+  Ok(days)
+}
+
+// Builds a lifecycle configuration from the given rules and the rules of an existing configuration
+// that weren't installed by drop.
+fn merge_lifecycle_rules(existing: &str, rules: &[(String, String, String, u64)]) -> String {
+  let mut body = "<LifecycleConfiguration>".to_string();
+  let mut rest = existing;
+  while let (Some(start), Some(end)) = (rest.find("<Rule>"), rest.find("</Rule>")) {
+    if end < start {
+      break;
+    }
+    let rule = &rest[start..end + "</Rule>".len()];
+    let drop_rule = extract_xml_value(rule, "ID").map(|id| id.starts_with(&format!("{}-", TTL_TAG))).unwrap_or(false);
+    if !drop_rule {
+      body.push_str(rule);
+    }
+    rest = &rest[end + "</Rule>".len()..];
+  }
+  for &(ref id, ref tag_key, ref tag_value, days) in rules {
+    body.push_str(&format!("<Rule><ID>{}</ID><Filter><Tag><Key>{}</Key><Value>{}</Value></Tag></Filter>\
+                            <Status>Enabled</Status><Expiration><Days>{}</Days></Expiration></Rule>",
+                           id, tag_key, tag_value, days));
+  }
+  body.push_str("</LifecycleConfiguration>");
+  body
+}
+
+// Lifecycle rules only work in whole days, so ttls are rounded up.
+fn ttl_days(ttl: u64) -> u64 {
+  ((ttl + 86399) / 86400).max(1)
+}
+
+fn ttl_tag_value(days: u64) -> String {
+  format!("{}d", days)
+}
+
+// Public url of an object, addressed the same way uploads are. Without a scheme in the configured
+// endpoint these stay on http like drop's urls always have.
+pub fn object_url(config: &DropConfig, key: &str) -> String {
+  let endpoint = util::with_scheme(&configured_endpoint(config), "http");
+  let bucket = config.aws_bucket.clone().unwrap_or_default();
+  if config.aws_path_style {
+    format!("{}/{}/{}", endpoint, bucket, key)
+  } else {
+    format!("{}/{}", virtual_hosted_endpoint(&endpoint, &bucket), key)
+  }
+}
+
+fn configured_endpoint(config: &DropConfig) -> String {
+  match config.aws_endpoint {
+    Some(ref endpoint) => endpoint.trim_end_matches('/').to_string(),
+    None if config.aws_region == DEFAULT_REGION => "s3.amazonaws.com".to_string(),
+    None => format!("s3.{}.amazonaws.com", config.aws_region),
+  }
+}
+
+fn virtual_hosted_endpoint(endpoint: &str, bucket: &str) -> String {
+  match endpoint.find("://") {
+    Some(idx) => format!("{}://{}.{}", &endpoint[..idx], bucket, &endpoint[idx + 3..]),
+    None => format!("{}.{}", bucket, endpoint),
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Client {
+  endpoint: String,
+  path_prefix: String,
+  region: String,
+  credentials: Credentials,
+}
+
+impl S3Client {
+  // Endpoints without a scheme default to https. With path style addressing the bucket is the first
+  // path segment, otherwise it is added to the endpoint host as a subdomain.
+  pub fn new(endpoint: &str, region: &str, bucket: &str, path_style: bool, credentials: Credentials) -> S3Client {
+    let endpoint = util::with_scheme(endpoint.trim_end_matches('/'), "https");
+    let (endpoint, path_prefix) =
+      if path_style {
+        (endpoint, format!("/{}", sigv4::uri_encode(bucket, true)))
+      } else {
+        (virtual_hosted_endpoint(&endpoint, bucket), String::new())
+      };
+
+    S3Client {
+      endpoint: endpoint,
+      path_prefix: path_prefix,
+      region: region.to_string(),
+      credentials: credentials,
+    }
+  }
+
+  pub fn from_config(config: &DropConfig) -> anyhow::Result<S3Client> {
+    let credentials = credentials::resolve(config)?;
+    Ok(S3Client::new(&configured_endpoint(config), &config.aws_region, &config.aws_bucket.clone().unwrap_or_default(),
+                     config.aws_path_style, credentials))
+  }
+
+  pub fn put_object(&self, key: &str, file_path: &Path, headers: &[(String, String)]) -> Result<(), S3Error> {
+    let payload_hash = util::sha256_file(file_path)?;
+    let length = file_path.metadata()?.len();
+    let file = File::open(file_path)?;
+
+    let request = self.request("PUT", key, &[], headers, &payload_hash)
+      .set("Content-Length", &length.to_string());
+    request.send(file)?;
+    Ok(())
+  }
+
+  pub fn presigned_get_url(&self, key: &str, expires: u64) -> String {
+    let path = format!("{}/{}", self.path_prefix, sigv4::uri_encode(key, false));
+    sigv4::presign_url(&self.credentials, &self.region, "s3", "GET", &self.endpoint, &self.host(), &path, expires, &Utc::now())
+  }
+
+  // Without s3:ListBucket permission S3 answers 403 rather than 404 for missing objects.
+  pub fn head_object(&self, key: &str) -> Result<bool, S3Error> {
+    match self.request("HEAD", key, &[], &[], &sigv4::sha256_hex(b"")).call() {
+      Ok(_) => Ok(true),
+      Err(ureq::Error::Status(404, _)) | Err(ureq::Error::Status(403, _)) => Ok(false),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  pub fn delete_object(&self, key: &str) -> Result<(), S3Error> {
+    self.request("DELETE", key, &[], &[], &sigv4::sha256_hex(b"")).call()?;
+    Ok(())
+  }
+
+  // Buckets without lifecycle rules answer 404 with NoSuchLifecycleConfiguration.
+  pub fn get_bucket_lifecycle(&self) -> Result<String, S3Error> {
+    let query = vec![("lifecycle".to_string(), "".to_string())];
+    match self.request("GET", "", &query, &[], &sigv4::sha256_hex(b"")).call() {
+      Ok(response) => Ok(response.into_string()?),
+      Err(ureq::Error::Status(404, _)) => Ok(String::new()),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  pub fn put_bucket_lifecycle(&self, body: &str) -> Result<(), S3Error> {
+    let query = vec![("lifecycle".to_string(), "".to_string())];
+    let headers = vec![("Content-MD5".to_string(), base64::engine::general_purpose::STANDARD.encode(Md5::digest(body.as_bytes())))];
+    self.request("PUT", "", &query, &headers, &sigv4::sha256_hex(body.as_bytes()))
+      .send_bytes(body.as_bytes())?;
+    Ok(())
   }
-  
-  let object_name = file_name.to_owned().unwrap_or(file_path.file_name().unwrap().to_string_lossy().into_owned());
-  let mut cmd = Command::new("s3cmd");
-  cmd.arg("--force")
-    .arg("--follow-symlinks")
-    .arg(format!("--access_key={}", config.aws_key.clone().unwrap()))
-    .arg(format!("--secret_key={}", config.aws_secret.clone().unwrap()))
-    .arg("put")
-    .arg(file_path.to_string_lossy().into_owned())
-    .arg(format!("s3://{}/{}", config.aws_bucket.clone().unwrap(), object_name));
 
-  let result = util::run_command_and_wait(&mut cmd, "S3CMD", config);
+  pub fn create_multipart_upload(&self, key: &str, headers: &[(String, String)]) -> Result<String, S3Error> {
+    let query = vec![("uploads".to_string(), "".to_string())];
+    let response = self.request("POST", key, &query, headers, &sigv4::sha256_hex(b""))
+      .send_bytes(&[])?;
+    let body = response.into_string()?;
+    extract_xml_value(&body, "UploadId").ok_or(S3Error::Response(200, body))
+  }
+
+  pub fn upload_part(&self, key: &str, upload_id: &str, part_number: u32, data: &[u8]) -> Result<String, S3Error> {
+    let query = vec![("partNumber".to_string(), part_number.to_string()),
+                     ("uploadId".to_string(), upload_id.to_string())];
+    let response = self.request("PUT", key, &query, &[], &sigv4::sha256_hex(data))
+      .send_bytes(data)?;
+    response.header("ETag").map(|etag| etag.to_string())
+      .ok_or(S3Error::Response(response.status(), "Missing ETag in upload part response".to_string()))
+  }
+
+  pub fn complete_multipart_upload(&self, key: &str, upload_id: &str, parts: &[(u32, String)]) -> Result<(), S3Error> {
+    let query = vec![("uploadId".to_string(), upload_id.to_string())];
+    let mut body = "<CompleteMultipartUpload>".to_string();
+    for &(number, ref etag) in parts {
+      body.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", number, etag));
+    }
+    body.push_str("</CompleteMultipartUpload>");
+
+    let response = self.request("POST", key, &query, &[], &sigv4::sha256_hex(body.as_bytes()))
+      .send_bytes(body.as_bytes())?;
+    let status = response.status();
+    let response_body = response.into_string()?;
+    // S3 can report a failed completion inside a 200 response
+    if response_body.contains("<Error>") {
+      Err(S3Error::Response(status, response_body))
+    } else {
+      Ok(())
+    }
+  }
+
+  fn request(&self, method: &str, key: &str, query: &[(String, String)], headers: &[(String, String)], payload_hash: &str) -> ureq::Request {
+    let path = format!("{}/{}", self.path_prefix, sigv4::uri_encode(key, false));
+    let signed_headers = sigv4::sign_request(&self.credentials, &self.region, "s3", method, &self.host(), &path,
+                                             query, headers, payload_hash, &Utc::now());
+
+    let query_string = sigv4::canonical_query_string(query);
+    let url =
+      if query_string.is_empty() {
+        format!("{}{}", self.endpoint, path)
+      } else {
+        format!("{}{}?{}", self.endpoint, path, query_string)
+      };
+
+    signed_headers.iter().fold(ureq::request(method, &url), |req, &(ref k, ref v)| req.set(k, v))
+  }
+
+  fn host(&self) -> String {
+    let authority = self.endpoint.splitn(2, "://").nth(1).unwrap_or(&self.endpoint);
+    let authority = authority.split('/').next().unwrap_or(authority);
+    if self.endpoint.starts_with("https://") {
+      authority.trim_end_matches(":443").to_string()
+    } else {
+      authority.trim_end_matches(":80").to_string()
+    }
+  }
+}
+
+fn extract_xml_value(xml: &str, tag: &str) -> Option<String> {
+  let open = format!("<{}>", tag);
+  let close = format!("</{}>", tag);
+  let start = xml.find(&open)? + open.len();
+  let end = xml[start..].find(&close)? + start;
+  Some(xml[start..end].to_string())
+}
+
+
+#[derive(Debug)]
+pub enum S3Error {
+  MissingFile(PathBuf),
+  Io(io::Error),
+  Transport(String),
+  Response(u16, String),
+}
+
+impl fmt::Display for S3Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      S3Error::MissingFile(ref path) => write!(f, "File does not exist, nothing to upload to S3! ({:?})", path),
+      S3Error::Io(ref err) => write!(f, "Failed to read file for S3 upload: {}", err),
+      S3Error::Transport(ref msg) => write!(f, "Failed to connect to S3: {}", msg),
+      S3Error::Response(status, ref body) => write!(f, "S3 request failed with status {}: {}", status, body),
+    }
+  }
+}
+
+impl ::std::error::Error for S3Error {}
+
+impl From<io::Error> for S3Error {
+  fn from(err: io::Error) -> S3Error {
+    S3Error::Io(err)
+  }
+}
+
+impl From<ureq::Error> for S3Error {
+  fn from(err: ureq::Error) -> S3Error {
+    match err {
+      ureq::Error::Status(status, response) => S3Error::Response(status, response.into_string().unwrap_or_default()),
+      ureq::Error::Transport(transport) => S3Error::Transport(transport.to_string()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testutil::{self, Response};
+  use util;
+  use std::env;
+  use std::fs;
+
+  fn client(endpoint: &str) -> S3Client {
+    S3Client::new(endpoint, "us-east-1", "drops", true,
+                  Credentials { access_key: "AKIDEXAMPLE".to_string(), secret_key: "secret".to_string(), session_token: None })
+  }
+
+  fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = env::temp_dir().join(format!("drop-aws-test-{}-{}", util::rand_string(8), name));
+    fs::write(&path, contents).unwrap();
+    path
+  }
+
+  #[test]
+  fn puts_objects_with_signed_headers() {
+    let file = temp_file("shot.png", b"not really a png");
+    let (url, server) = testutil::serve(vec![Response::new(200, "")]);
+
+    client(&url).put_object("shot 1.png", &file, &[("Content-Type".to_string(), "image/png".to_string())]).unwrap();
+
+    let requests = server.join().unwrap();
+    let request = &requests[0];
+    assert_eq!(request.method, "PUT");
+    assert_eq!(request.path, "/drops/shot%201.png");
+    assert_eq!(request.body, b"not really a png");
+    assert_eq!(request.header("x-amz-content-sha256"), Some(sigv4::sha256_hex(b"not really a png").as_str()));
+    let authorization = request.header("authorization").unwrap();
+    assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+    assert!(authorization.contains("/us-east-1/s3/aws4_request, SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date, Signature="));
+    fs::remove_file(file).unwrap();
+  }
+
+  #[test]
+  fn reports_rejected_uploads() {
+    let file = temp_file("shot.png", b"data");
+    let (url, server) = testutil::serve(vec![Response::new(403, "<Error><Code>AccessDenied</Code></Error>")]);
+
+    match client(&url).put_object("shot.png", &file, &[]) {
+      Err(S3Error::Response(403, body)) => assert!(body.contains("AccessDenied")),
+      other => panic!("expected a 403 response error, got {:?}", other),
+    }
+    server.join().unwrap();
+    fs::remove_file(file).unwrap();
+  }
+
+  #[test]
+  fn builds_object_urls() {
+    let cases = vec![
+      (DropConfig::builder(), "http://drops.s3.amazonaws.com/shot.png"),
+      (DropConfig::builder().aws_region("eu-west-1"), "http://drops.s3.eu-west-1.amazonaws.com/shot.png"),
+      (DropConfig::builder().aws_region("eu-west-1").aws_path_style(true), "http://s3.eu-west-1.amazonaws.com/drops/shot.png"),
+      (DropConfig::builder().aws_endpoint("localhost:9000"), "http://localhost:9000/drops/shot.png"),
+      (DropConfig::builder().aws_endpoint("https://minio.example.com/"), "https://minio.example.com/drops/shot.png"),
+      (DropConfig::builder().aws_endpoint("https://minio.example.com").aws_path_style(false), "https://drops.minio.example.com/shot.png"),
+      (DropConfig::builder().aws_endpoint("s3.wasabisys.com").aws_path_style(false), "http://drops.s3.wasabisys.com/shot.png"),
+    ];
+    for (builder, expected) in cases {
+      let config = builder.aws_bucket("drops").build().unwrap();
+      assert_eq!(object_url(&config, "shot.png"), expected);
+    }
+  }
+
+  #[test]
+  fn addresses_buckets_by_path_or_subdomain() {
+    let credentials = Credentials { access_key: "AKIDEXAMPLE".to_string(), secret_key: "secret".to_string(), session_token: None };
+    let client = S3Client::new("localhost:9000", "us-east-1", "drops", true, credentials.clone());
+    assert_eq!((client.endpoint.as_str(), client.path_prefix.as_str(), client.host()), ("https://localhost:9000", "/drops", "localhost:9000".to_string()));
+
+    let client = S3Client::new("s3.eu-west-1.amazonaws.com", "eu-west-1", "drops", false, credentials.clone());
+    assert_eq!((client.endpoint.as_str(), client.path_prefix.as_str(), client.host()),
+               ("https://drops.s3.eu-west-1.amazonaws.com", "", "drops.s3.eu-west-1.amazonaws.com".to_string()));
+
+    let client = S3Client::new("http://minio.local:80/", "us-east-1", "my drops", true, credentials);
+    assert_eq!((client.endpoint.as_str(), client.path_prefix.as_str(), client.host()), ("http://minio.local:80", "/my%20drops", "minio.local".to_string()));
+  }
+
+  #[test]
+  fn defaults_to_path_style_only_for_custom_endpoints() {
+    assert!(!DropConfig::builder().build().unwrap().aws_path_style);
+    assert!(DropConfig::builder().aws_endpoint("http://localhost:9000").build().unwrap().aws_path_style);
+    assert!(DropConfig::builder().aws_path_style(true).build().unwrap().aws_path_style);
+    assert!(!DropConfig::builder().aws_endpoint("http://localhost:9000").aws_path_style(false).build().unwrap().aws_path_style);
+  }
+
+  #[test]
+  fn keeps_other_lifecycle_rules() {
+    let existing = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                    <LifecycleConfiguration xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+                    <Rule><ID>logs</ID><Filter><Prefix>logs/</Prefix></Filter><Status>Enabled</Status><Expiration><Days>30</Days></Expiration></Rule>\
+                    <Rule><ID>drop-ttl-1d</ID><Filter><Tag><Key>drop-ttl</Key><Value>1d</Value></Tag></Filter><Status>Enabled</Status><Expiration><Days>1</Days></Expiration></Rule>\
+                    </LifecycleConfiguration>";
+    let rules = vec![("drop-ttl-7d".to_string(), "drop-ttl".to_string(), "7d".to_string(), 7)];
+
+    assert_eq!(merge_lifecycle_rules(existing, &rules),
+               "<LifecycleConfiguration>\
+                <Rule><ID>logs</ID><Filter><Prefix>logs/</Prefix></Filter><Status>Enabled</Status><Expiration><Days>30</Days></Expiration></Rule>\
+                <Rule><ID>drop-ttl-7d</ID><Filter><Tag><Key>drop-ttl</Key><Value>7d</Value></Tag></Filter><Status>Enabled</Status><Expiration><Days>7</Days></Expiration></Rule>\
+                </LifecycleConfiguration>");
+    assert_eq!(merge_lifecycle_rules("", &[]), "<LifecycleConfiguration></LifecycleConfiguration>");
+  }
+
+  #[test]
+  fn reads_missing_lifecycle_configurations_as_empty() {
+    let (url, server) = testutil::serve(vec![Response::new(404, "<Error><Code>NoSuchLifecycleConfiguration</Code></Error>"),
+                                             Response::new(200, "")]);
+
+    let client = client(&url);
+    assert_eq!(client.get_bucket_lifecycle().unwrap(), "");
+    client.put_bucket_lifecycle("<LifecycleConfiguration></LifecycleConfiguration>").unwrap();
+
+    let requests = server.join().unwrap();
+    assert_eq!((requests[0].method.as_str(), requests[0].path.as_str()), ("GET", "/drops/?lifecycle="));
+    assert_eq!((requests[1].method.as_str(), requests[1].path.as_str()), ("PUT", "/drops/?lifecycle="));
+    assert_eq!(requests[1].body, b"<LifecycleConfiguration></LifecycleConfiguration>");
+    assert!(requests[1].header("content-md5").is_some());
+  }
 
-  if !result.success() {
-    println!("ERROR: File failed to upload properly to S3 ({:?})", file_path);
-    std::process::exit(1);
+  #[test]
+  fn reports_missing_files() {
+    let file = env::temp_dir().join("drop-aws-test-missing");
+    assert!(match client("http://127.0.0.1:1").put_object("missing", &file, &[]) {
+      Err(S3Error::Io(_)) => true,
+      _ => false,
+    });
   }
 }
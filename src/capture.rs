@@ -1,13 +1,14 @@
 use ui;
 use conf::DropConfig;
+use error::DropError;
 use util;
 
-use std;
 use std::env;
 use std::io;
 use std::fs;
 use std::process::{Command, Child, ExitStatus};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
 use nix::sys::signal::{kill, Signal};
 use nix::unistd::Pid;
 use sys_info;
@@ -28,8 +29,24 @@ extern {
   static AVMediaTypeAudio: Id;
 }
 
+// Takes a screenshot into a newly named png in the drop directory.
+pub fn capture_screenshot(config: &DropConfig) -> Result<PathBuf> {
+  let out_file_name = util::generate_filename(config, None, Some("png".to_string()));
+  let out_file = Path::new(&config.dir).join(out_file_name);
+  screenshot(out_file.as_path(), config)?;
+  Ok(out_file)
+}
+
+// Records a screencast into a newly named file in the drop directory, in the configured video format.
+pub fn capture_screencast(config: &DropConfig) -> Result<PathBuf> {
+  let out_file_name = util::generate_filename(config, None, Some(config.video_format.clone()));
+  let out_file = Path::new(&config.dir).join(out_file_name);
+  screencast(out_file.as_path(), config)?;
+  Ok(out_file)
+}
+
 #[cfg(target_os = "linux")]
-pub fn screenshot(out_path: &Path, config: &DropConfig) {
+pub fn screenshot(out_path: &Path, config: &DropConfig) -> Result<()> {
   if config.display_server == "wayland" {
     screenshot_wayland(out_path, config)
   } else {
@@ -37,21 +54,21 @@ pub fn screenshot(out_path: &Path, config: &DropConfig) {
   }
 }
 
-pub fn screenshot_wayland(out_path: &Path, config: &DropConfig) {
-  let slurp_out = run_slurp(config);
+pub fn screenshot_wayland(out_path: &Path, config: &DropConfig) -> Result<()> {
+  let slurp_out = run_slurp(config)?;
   util::wait_delay(config);
   crop_and_save_screenshot_wayland(slurp_out, out_path, config)
 }
 
-pub fn screenshot_x11(out_path: &Path, config: &DropConfig) {
-  let slop_out = run_slop(config);
+pub fn screenshot_x11(out_path: &Path, config: &DropConfig) -> Result<()> {
+  let slop_out = run_slop(config)?;
   util::wait_delay(config);
-  crop_and_save_screenshot_x11(&slop_out, out_path, config);
+  crop_and_save_screenshot_x11(&slop_out, out_path, config)
 }
 
 
 #[cfg(target_os = "linux")]
-pub fn screencast(out_path: &Path, config: &DropConfig) {
+pub fn screencast(out_path: &Path, config: &DropConfig) -> Result<()> {
   if config.display_server == "wayland" {
     screencast_wayland(out_path, config)
   } else {
@@ -59,89 +76,88 @@ pub fn screencast(out_path: &Path, config: &DropConfig) {
   }
 }
 
-pub fn screencast_x11(out_path: &Path, config: &DropConfig) {
-  let slop_out = run_slop(config);
+pub fn screencast_x11(out_path: &Path, config: &DropConfig) -> Result<()> {
+  let slop_out = run_slop(config)?;
   util::wait_delay(config);
   let process =
     if config.video_format == "gif" {
       start_cropped_screencast_process_gif(&slop_out, out_path, config)
     } else {
       start_cropped_screencast_process(&slop_out, out_path, config)
-    };
+    }.context(DropError::Capture("Failed to start ffmpeg".to_string()))?;
 
-  ui::wait_for_user_stop(config);
+  let stopped = ui::wait_for_user_stop(config);
   let result = terminate_record_process(process);
+  stopped?;
+  result.context(DropError::Capture("Failed to record screencast".to_string()))?;
 
   if config.video_format == "gif" {
-    post_process_screencast_gif(out_path, config)
-  }
-
-  if result.is_err() {
-    println!("ERROR: Failed to record screencast");
-    std::process::exit(1);
+    post_process_screencast_gif(out_path, config)?;
   }
+  Ok(())
 }
 
-pub fn screencast_wayland(out_path: &Path, config: &DropConfig) {
-  let slurp_out = run_slurp(config);
+pub fn screencast_wayland(out_path: &Path, config: &DropConfig) -> Result<()> {
+  let slurp_out = run_slurp(config)?;
   util::wait_delay(config);
-  let process = start_cropped_screencast_process_wayland(slurp_out, out_path, config);
+  let process = start_cropped_screencast_process_wayland(slurp_out, out_path, config)
+    .context(DropError::Capture("Failed to start wf-recorder".to_string()))?;
 
-  ui::wait_for_user_stop(config);
-  println!("Terminated Record, waiting...");
+  let stopped = ui::wait_for_user_stop(config);
   let result = terminate_record_process(process);
-  println!("Termination complete");
+  stopped?;
+  result.context(DropError::Capture("Failed to record screencast".to_string()))?;
 
   if config.video_format == "gif" {
-    post_process_screencast_gif(out_path, config)
-  }
-
-  if result.is_err() {
-    println!("ERROR: Failed to record screencast");
-    std::process::exit(1);
+    post_process_screencast_gif(out_path, config)?;
   }
+  Ok(())
 }
 
 #[cfg(target_os = "macos")]
-pub fn screenshot(out_path: &Path, config: &DropConfig) {
+pub fn screenshot(out_path: &Path, config: &DropConfig) -> Result<()> {
   let mut cmd = Command::new("screencapture");
   if (config.delay > 0) {
     cmd.args(&["-T", &config.delay.to_string()]);
   }
   cmd.args(&["-s", &out_path.to_string_lossy().into_owned()]);
-  let result = util::run_command_and_wait(&mut cmd, "SCREEN CAPTURE", config);
+  let result = util::run_command_and_wait(&mut cmd, "SCREEN CAPTURE", config)
+    .context(DropError::Capture("Failed to run screencapture".to_string()))?;
 
   if !result.success() {
-    println!("Cancelling drop, exiting");
-    std::process::exit(1);
+    return Err(DropError::Cancelled.into());
   }
+  Ok(())
 }
 
 #[cfg(target_os = "macos")]
-pub fn crop_and_take_screencast(out_path: &Path, config: &DropConfig) {
+pub fn crop_and_take_screencast(out_path: &Path, config: &DropConfig) -> Result<()> {
   util::wait_delay(config);
   let capture_session = create_and_initiate_macos_caputure_session(out_path, config);
-  ui::wait_for_user_stop();
+  let stopped = ui::wait_for_user_stop();
   end_macos_capture_session(capture_session);
+  stopped
 }
 
-fn run_slop(config: &DropConfig) -> SlopOutput {
+fn run_slop(config: &DropConfig) -> Result<SlopOutput> {
   let result =
     if config.transparent {
-      Command::new("slop").args(&["-l", "-c", "0.3,0.4,0.6,0.4", "-f", "%x %y %w %h %g %i"]).output().unwrap()
+      Command::new("slop").args(&["-l", "-c", "0.3,0.4,0.6,0.4", "-f", "%x %y %w %h %g %i"]).output()
     } else {
-      Command::new("slop").args(&["-b", "5", "-c", "0.3,0.4,0.6,1", "-f", "%x %y %w %h %g %i"]).output().unwrap()
-    };
+      Command::new("slop").args(&["-b", "5", "-c", "0.3,0.4,0.6,1", "-f", "%x %y %w %h %g %i"]).output()
+    }.context(DropError::Capture("Failed to run slop, is it installed?".to_string()))?;
 
   if !result.status.success() {
-    println!("Cancelled drop, exiting");
-    std::process::exit(1);
+    return Err(DropError::Cancelled.into());
   }
 
-  let output = String::from_utf8(result.stdout).unwrap();
+  let output = String::from_utf8_lossy(&result.stdout).into_owned();
   let split: Vec<&str> = output.trim().split(" ").collect();
+  if split.len() < 6 {
+    return Err(DropError::Capture(format!("Unexpected output from slop: {}", output.trim())).into());
+  }
 
-  SlopOutput {
+  Ok(SlopOutput {
     x: split[0].to_string(),
     y: split[1].to_string(),
     w: split[2].to_string(),
@@ -149,48 +165,39 @@ fn run_slop(config: &DropConfig) -> SlopOutput {
     g: split[4].to_string(),
     id: split[5].to_string(),
     cancel: false,
-  }
+  })
 }
 
-fn run_slurp(config: &DropConfig) -> String {
-  let result = Command::new("slurp").output().unwrap();
+fn run_slurp(config: &DropConfig) -> Result<String> {
+  let result = Command::new("slurp").output()
+    .context(DropError::Capture("Failed to run slurp, is it installed?".to_string()))?;
 
   if !result.status.success() {
-    println!("Cancelled drop, exiting");
-    std::process::exit(1);
+    return Err(DropError::Cancelled.into());
   }
 
-  String::from_utf8(result.stdout).unwrap()
+  Ok(String::from_utf8_lossy(&result.stdout).into_owned())
 }
 
-fn crop_and_save_screenshot_x11(slop_out: &SlopOutput, out_path: &Path, config: &DropConfig) {
+fn crop_and_save_screenshot_x11(slop_out: &SlopOutput, out_path: &Path, config: &DropConfig) -> Result<()> {
   let mut cmd = Command::new("import");
   cmd.args(&["-window", "root",
              "-crop", &slop_out.g,
              &out_path.to_string_lossy().into_owned()]);
 
   let result = util::run_command_and_wait(&mut cmd, "IMPORT", config);
-
-  if !result.success() {
-    println!("ERROR: Failed to take the screenshot");
-    std::process::exit(1);
-  }
+  check_capture_status(result, "Failed to take the screenshot")
 }
 
-fn crop_and_save_screenshot_wayland(slurp_out: String, out_path: &Path, config: &DropConfig) {
+fn crop_and_save_screenshot_wayland(slurp_out: String, out_path: &Path, config: &DropConfig) -> Result<()> {
   let mut cmd = Command::new("grim");
   cmd.args(&["-g", slurp_out.trim(), &out_path.to_string_lossy().into_owned()]);
 
-  println!("Command: {:?}", cmd);
   let result = util::run_command_and_wait(&mut cmd, "GRIM", config);
-
-  if !result.success() {
-    println!("ERROR: Failed to take the screenshot");
-    std::process::exit(1);
-  }
+  check_capture_status(result, "Failed to take the screenshot")
 }
 
-fn start_cropped_screencast_process(slop_out: &SlopOutput, out_path: &Path, config: &DropConfig) -> Child {
+fn start_cropped_screencast_process(slop_out: &SlopOutput, out_path: &Path, config: &DropConfig) -> io::Result<Child> {
   let mut cmd = Command::new("ffmpeg");
   let display = match env::var("DISPLAY") {
     Ok(display) => display,
@@ -230,15 +237,14 @@ fn start_cropped_screencast_process(slop_out: &SlopOutput, out_path: &Path, conf
   util::run_command(&mut cmd, "FFMPEG", config)
 }
 
-fn start_cropped_screencast_process_wayland(slurp_out: String, out_path: &Path, config: &DropConfig) -> Child {
+fn start_cropped_screencast_process_wayland(slurp_out: String, out_path: &Path, config: &DropConfig) -> io::Result<Child> {
   let mut cmd = Command::new("wf-recorder");
   cmd.args(&["-g", slurp_out.trim(), "--file", &out_path.to_string_lossy().into_owned()]);
-  println!("command: {:?}", cmd);
   util::run_command(&mut cmd, "WF-RECORDER", config)
 }
 
 
-fn start_cropped_screencast_process_gif(slop_out: &SlopOutput, out_path: &Path, config: &DropConfig) -> Child {
+fn start_cropped_screencast_process_gif(slop_out: &SlopOutput, out_path: &Path, config: &DropConfig) -> io::Result<Child> {
   let pamfile = &out_path.to_string_lossy().into_owned().replace(".gif", ".pam");
   let mut cmd = Command::new("ffmpeg");
   cmd.args(&["-f", "x11grab",
@@ -253,27 +259,32 @@ fn start_cropped_screencast_process_gif(slop_out: &SlopOutput, out_path: &Path,
   util::run_command(&mut cmd, "FFMPEG", config)
 }
 
-fn post_process_screencast_gif(out_path: &Path, config: &DropConfig) {
-  let memory_limit = ((sys_info::mem_info().unwrap().avail as f64 * 0.6) as f64) as u64;
+fn post_process_screencast_gif(out_path: &Path, config: &DropConfig) -> Result<()> {
+  let memory_limit = sys_info::mem_info().map(|info| (info.avail as f64 * 0.6) as u64)
+    .context(DropError::Capture("Failed to read available memory".to_string()))?;
   let cache_id = util::rand_string(30);
-  let cachedir = Path::new(&config.dir).join(".cache").join(cache_id);
-  let pamfile = out_path.to_str().unwrap().replace(".gif", ".pam");
+  let cachedir = Path::new(&config.cache_dir).join(cache_id);
+  let pamfile = util::path_to_str(out_path).replace(".gif", ".pam");
   let mut process_cmd = Command::new("convert");
-  println!("{}kiB", memory_limit);
   process_cmd.args(&["-set", "delay", "5",
                      "-limit", "disk", "unlimited",
                      "-limit", "memory", &format!("{}kiB", memory_limit),
                      "-layers", "Optimize",
-                     "-define", &format!("registry:temporary-path={}", cachedir.to_str().unwrap()),
+                     "-define", &format!("registry:temporary-path={}", util::path_to_str(&cachedir)),
                      &pamfile,
-                     out_path.to_str().unwrap()]);
+                     &util::path_to_str(out_path)]);
 
   let result = util::run_command_and_wait(&mut process_cmd, "FFMPEG", config);
-  fs::remove_file(pamfile);
-  fs::remove_dir_all(cachedir);
-  if !result.success() {
-    println!("ERROR: Failed to generate gif");
-    std::process::exit(1);
+  let _ = fs::remove_file(pamfile);
+  let _ = fs::remove_dir_all(cachedir);
+  check_capture_status(result, "Failed to generate gif")
+}
+
+fn check_capture_status(result: io::Result<ExitStatus>, msg: &str) -> Result<()> {
+  match result {
+    Ok(ref status) if status.success() => Ok(()),
+    Ok(_) => Err(DropError::Capture(msg.to_string()).into()),
+    Err(err) => Err(anyhow::Error::new(err).context(DropError::Capture(msg.to_string()))),
   }
 }
 
@@ -281,7 +292,7 @@ fn terminate_record_process(mut child: Child) -> io::Result<ExitStatus> {
   let child_id = child.id();
   let result = kill(Pid::from_raw(child_id as i32), Signal::SIGINT);
   if result.is_err() {
-    println!("WARNING: Failed to propertly terminate record process")
+    eprintln!("WARNING: Failed to propertly terminate record process")
   }
   child.wait()
 }
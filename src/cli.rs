@@ -1,4 +1,4 @@
-use clap::{Arg, App};
+use clap::{Arg, App, SubCommand};
 
 pub fn create_drop_cli_app() -> App<'static,'static> {
   App::new("drop")
@@ -9,6 +9,9 @@ pub fn create_drop_cli_app() -> App<'static,'static> {
          .short("-a")
          .long("audio")
          .help("Enable audio in screencast"))
+    .arg(Arg::with_name("attachment")
+         .long("attachment")
+         .help("Serve the upload as a download with its original filename (Content-Disposition: attachment)"))
     .arg(Arg::with_name("audio-source")
          .long("audio-source")
          .value_name("AUDIO_SOURCE")
@@ -16,6 +19,12 @@ pub fn create_drop_cli_app() -> App<'static,'static> {
          .takes_value(true)
          .possible_values(&["mic", "desktop"])
          .default_value("mic"))
+    .arg(Arg::with_name("backend")
+         .long("backend")
+         .value_name("BACKEND")
+         .help("Remote backend to upload to")
+         .possible_values(&["s3", "sftp", "http", "local"])
+         .takes_value(true))
     .arg(Arg::with_name("border")
          .short("-b")
          .long("border")
@@ -25,16 +34,36 @@ pub fn create_drop_cli_app() -> App<'static,'static> {
          .value_name("AWS_BUCKET")
          .help("S3 Bucket to upload to")
          .takes_value(true))
+    .arg(Arg::with_name("aws-endpoint")
+         .long("aws-endpoint")
+         .value_name("AWS_ENDPOINT")
+         .help("S3 compatible endpoint to upload to, eg. http://localhost:9000")
+         .takes_value(true))
     .arg(Arg::with_name("aws-key")
          .long("aws-key")
          .value_name("AWS_KEY")
          .help("AWS access key")
          .takes_value(true))
+    .arg(Arg::with_name("aws-profile")
+         .long("aws-profile")
+         .value_name("AWS_PROFILE")
+         .help("Profile in ~/.aws/credentials or ~/.aws/config to take AWS credentials from")
+         .takes_value(true))
+    .arg(Arg::with_name("aws-region")
+         .long("aws-region")
+         .value_name("AWS_REGION")
+         .help("Region of the S3 bucket")
+         .takes_value(true))
     .arg(Arg::with_name("aws-secret")
          .long("aws-secret")
          .value_name("AWS_SECRET")
          .help("AWS access secret")
          .takes_value(true))
+    .arg(Arg::with_name("config")
+         .long("config")
+         .value_name("PATH")
+         .help("Config file to use instead of $DROP_CONFIG or $XDG_CONFIG_HOME/drop/config.toml")
+         .takes_value(true))
     .arg(Arg::with_name("delay")
          .long("delay")
          .short("-d")
@@ -42,6 +71,11 @@ pub fn create_drop_cli_app() -> App<'static,'static> {
          .help("Number of seconds to delay screenshot or screencast start.")
          .takes_value(true)
          .default_value("0"))
+    .arg(Arg::with_name("expires")
+         .long("expires")
+         .value_name("DURATION")
+         .help("Produce a presigned S3 url that expires after the given duration, eg. 24h (max 7d)")
+         .takes_value(true))
     .arg(Arg::with_name("extension")
          .long("extension")
          .short("-e")
@@ -62,7 +96,18 @@ pub fn create_drop_cli_app() -> App<'static,'static> {
          .long("filename-strategy")
          .value_name("STRATEGY")
          .help("File upload naming strategy")
-         .possible_values(&["exact", "append", "prepend", "replace"])
+         .possible_values(&["exact", "append", "prepend", "replace", "hash"])
+         .takes_value(true))
+    .arg(Arg::with_name("format")
+         .long("format")
+         .value_name("FORMAT")
+         .help("Markup to wrap the copied and printed url in, custom uses drop.format_template from the config")
+         .possible_values(&["raw", "markdown", "html", "bbcode", "org", "custom"])
+         .takes_value(true))
+    .arg(Arg::with_name("filename-template")
+         .long("filename-template")
+         .value_name("TEMPLATE")
+         .help("Template for naming uploads, eg. {date:%Y/%m}/{orig}-{rand:6}.{ext}")
          .takes_value(true))
     .arg(Arg::with_name("host")
          .long("host")
@@ -77,6 +122,20 @@ pub fn create_drop_cli_app() -> App<'static,'static> {
          .short("-m")
          .long("mouse")
          .help("Show mouse cursor in screencast."))
+    .arg(Arg::with_name("output")
+         .long("output")
+         .short("-o")
+         .value_name("FORMAT")
+         .help("Output format, json prints the url, key, size, type, backend and timing of the drop as one object")
+         .possible_values(&["text", "json"])
+         .default_value("text")
+         .takes_value(true))
+    .arg(Arg::with_name("profile")
+         .long("profile")
+         .short("-p")
+         .value_name("PROFILE")
+         .help("Named destination profile from the config to drop to, overrides drop.default_profile")
+         .takes_value(true))
     .arg(Arg::with_name("quiet")
          .long("quiet")
          .short("-q")
@@ -89,16 +148,32 @@ pub fn create_drop_cli_app() -> App<'static,'static> {
          .long("no-tray-icon")
          .short("-i")
          .help("Do not display tray icon while recording screencast."))
+    .arg(Arg::with_name("ttl")
+         .long("ttl")
+         .value_name("DURATION")
+         .help("Time after which the drop expires and is removed by 'drop gc', eg. 7d")
+         .takes_value(true))
     .arg(Arg::with_name("unique-length")
          .short("-u")
          .long("unique-length")
          .value_name("LENGTH")
          .help("Length of unique string used to create filenames")
          .takes_value(true))
+    .arg(Arg::with_name("unique-style")
+         .long("unique-style")
+         .value_name("STYLE")
+         .help("Style of unique string used to create filenames, words produces ids like brave-otter-lamp")
+         .possible_values(&["alphanumeric", "words", "base32"])
+         .takes_value(true))
     .arg(Arg::with_name("transparent")
          .long("transparent")
          .short("-t")
          .help("Enable transparent selection overlay, compositor is required (Linux only)"))
+    .arg(Arg::with_name("url-template")
+         .long("url-template")
+         .value_name("TEMPLATE")
+         .help("Template for generated urls, eg. https://cdn.example.com/{yyyy}/{mm}/{key}")
+         .takes_value(true))
     .arg(Arg::with_name("verbose")
          .long("verbose")
          .help("Enables verbose logging"))
@@ -119,4 +194,64 @@ pub fn create_drop_cli_app() -> App<'static,'static> {
          .help("Indicates which display server to target (Linux only - Defaults to $XDG_SESSION_TYPE or x11)")
          .possible_values(&["x11", "wayland"])
          .takes_value(true))
+    .subcommand(SubCommand::with_name("history")
+                .about("Show previous drops (lists the most recent by default)")
+                .subcommand(SubCommand::with_name("list")
+                            .about("List the most recent drops")
+                            .arg(Arg::with_name("count")
+                                 .short("-n")
+                                 .long("count")
+                                 .value_name("COUNT")
+                                 .help("Number of drops to list")
+                                 .takes_value(true)
+                                 .default_value("20")))
+                .subcommand(SubCommand::with_name("search")
+                            .about("Search drops by url, key, local path or type")
+                            .arg(Arg::with_name("query")
+                                 .value_name("QUERY")
+                                 .required(true)
+                                 .index(1)))
+                .subcommand(SubCommand::with_name("show")
+                            .about("Show all details of a drop")
+                            .arg(Arg::with_name("id")
+                                 .value_name("ID")
+                                 .required(true)
+                                 .index(1))))
+    .subcommand(SubCommand::with_name("last")
+                .about("Copy the url of the most recent drop to the clipboard"))
+    .subcommand(SubCommand::with_name("delete")
+                .about("Delete a previous drop from remote storage")
+                .arg(Arg::with_name("target")
+                     .value_name("URL|KEY|ID")
+                     .help("Url, remote key or history id of the drop to delete")
+                     .required_unless("last")
+                     .index(1))
+                .arg(Arg::with_name("last")
+                     .long("last")
+                     .help("Delete the most recent drop")
+                     .conflicts_with("target")))
+    .subcommand(SubCommand::with_name("gc")
+                .about("Delete expired drops from remote storage")
+                .arg(Arg::with_name("dry-run")
+                     .long("dry-run")
+                     .help("List expired drops without deleting them"))
+                .arg(Arg::with_name("install-lifecycle")
+                     .long("install-lifecycle")
+                     .help("Install S3 lifecycle rules that expire drops by their ttl tag")))
+    .subcommand(SubCommand::with_name("clean")
+                .about("Remove local drops that fall outside the retention policy")
+                .arg(Arg::with_name("dry-run")
+                     .long("dry-run")
+                     .help("List the files that would be removed without removing them")))
+    .subcommand(SubCommand::with_name("config")
+                .about("Show, check or create the config file (shows the effective config by default)")
+                .subcommand(SubCommand::with_name("show")
+                            .about("Show the effective config after profiles and flags are applied, with secrets masked"))
+                .subcommand(SubCommand::with_name("check")
+                            .about("Check the config file for unknown keys and invalid values"))
+                .subcommand(SubCommand::with_name("init")
+                            .about("Write the default config file")
+                            .arg(Arg::with_name("force")
+                                 .long("force")
+                                 .help("Overwrite an existing config file"))))
 }
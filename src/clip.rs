@@ -3,22 +3,24 @@ use std::io::Write;
 
 #[cfg(target_os = "linux")]
 pub fn copy_to_clipboard(url: String) {
-  let mut process = Command::new("xsel").arg("--clipboard").stdin(Stdio::piped()).spawn().unwrap();
-  let write_result = process.stdin.as_mut().unwrap().write_all(url.as_bytes());
-  let result = process.wait();
-  
-  if result.is_err() || write_result.is_err() || !result.unwrap().success() {
-    println!("WARNING: Failed to copy url to clipboard: {}", url);
+  let result = Command::new("xsel").arg("--clipboard").stdin(Stdio::piped()).spawn().and_then(|mut process| {
+    let write_result = process.stdin.take().map(|mut stdin| stdin.write_all(url.as_bytes())).unwrap_or(Ok(()));
+    process.wait().and_then(|status| write_result.map(|_| status))
+  });
+
+  if !result.map(|status| status.success()).unwrap_or(false) {
+    eprintln!("WARNING: Failed to copy url to clipboard: {}", url);
   }
 }
 
 #[cfg(target_os = "macos")]
 pub fn copy_to_clipboard(url: String) {
-  let mut process = Command::new("pbcopy").stdin(Stdio::piped()).spawn().unwrap();
-  let write_result = process.stdin.as_mut().unwrap().write_all(url.as_bytes());
-  let result = process.wait();
+  let result = Command::new("pbcopy").stdin(Stdio::piped()).spawn().and_then(|mut process| {
+    let write_result = process.stdin.take().map(|mut stdin| stdin.write_all(url.as_bytes())).unwrap_or(Ok(()));
+    process.wait().and_then(|status| write_result.map(|_| status))
+  });
 
-  if result.is_err() || write_result.is_err() || !result.unwrap().success() {
-    println!("WARNING: Failed to copy url to clipboard: {}", url);
+  if !result.map(|status| status.success()).unwrap_or(false) {
+    eprintln!("WARNING: Failed to copy url to clipboard: {}", url);
   }
 }
@@ -0,0 +1,292 @@
+use conf::DropConfig;
+use sigv4::Credentials;
+use util;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use anyhow::{Context, Result};
+use serde_json;
+
+const DEFAULT_PROFILE: &'static str = "default";
+
+// Credentials are looked up in the same order as the AWS tools, except that keys given to drop itself
+// come first: the [aws] config (with secret_command), the AWS_* environment variables, then the
+// profile's keys or credential_process in ~/.aws/credentials and ~/.aws/config.
+pub fn resolve(config: &DropConfig) -> Result<Credentials> {
+  if let Some(ref access_key) = config.aws_key {
+    let secret_key = match (&config.aws_secret, &config.aws_secret_command) {
+      (&Some(ref secret), _) => secret.clone(),
+      (&None, &Some(ref command)) => run_secret_command(command)?,
+      (&None, &None) => return Err(anyhow!("aws.key is configured without aws.secret or aws.secret_command")),
+    };
+    return Ok(Credentials { access_key: access_key.clone(), secret_key: secret_key, session_token: config.aws_session_token.clone() });
+  }
+
+  if let (Some(access_key), Some(secret_key)) = (env_value("AWS_ACCESS_KEY_ID"), env_value("AWS_SECRET_ACCESS_KEY")) {
+    return Ok(Credentials { access_key: access_key, secret_key: secret_key, session_token: env_value("AWS_SESSION_TOKEN") });
+  }
+
+  let profile = profile_name(config);
+  let section = profile_section(&profile)
+    .ok_or(anyhow!("No AWS credentials found, configure aws.key and aws.secret, set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, \
+                    or add a {} profile to ~/.aws/credentials", profile))?;
+
+  if let (Some(access_key), Some(secret_key)) = (section_value(&section, "aws_access_key_id"), section_value(&section, "aws_secret_access_key")) {
+    return Ok(Credentials { access_key: access_key, secret_key: secret_key, session_token: section_value(&section, "aws_session_token") });
+  }
+  match section_value(&section, "credential_process") {
+    Some(command) => run_credential_process(&command),
+    None => Err(anyhow!("AWS profile {} has neither access keys nor a credential_process", profile)),
+  }
+}
+
+// A cheap check that doesn't run any commands, used to pick the s3 backend by default. A profile only
+// counts when it can provide credentials, a [default] profile with just a region is common.
+pub fn available(config: &DropConfig) -> bool {
+  (config.aws_key.is_some() && (config.aws_secret.is_some() || config.aws_secret_command.is_some()))
+    || (env_value("AWS_ACCESS_KEY_ID").is_some() && env_value("AWS_SECRET_ACCESS_KEY").is_some())
+    || profile_section(&profile_name(config)).map_or(false, |section| {
+      (section_value(&section, "aws_access_key_id").is_some() && section_value(&section, "aws_secret_access_key").is_some())
+        || section_value(&section, "credential_process").is_some()
+    })
+}
+
+fn profile_name(config: &DropConfig) -> String {
+  config.aws_profile.clone().or(env_value("AWS_PROFILE")).unwrap_or(DEFAULT_PROFILE.to_string())
+}
+
+// Sections from the credentials file take precedence over the config file, where every profile but
+// the default one is written as [profile <name>].
+fn profile_section(profile: &str) -> Option<Vec<(String, String)>> {
+  let config_section = if profile == DEFAULT_PROFILE { profile.to_string() } else { format!("profile {}", profile) };
+  let mut values = Vec::new();
+  if let Some(section) = read_section(shared_file("AWS_SHARED_CREDENTIALS_FILE", "credentials"), profile) {
+    values.extend(section);
+  }
+  if let Some(section) = read_section(shared_file("AWS_CONFIG_FILE", "config"), &config_section) {
+    values.extend(section);
+  }
+  if values.is_empty() { None } else { Some(values) }
+}
+
+fn shared_file(env_key: &str, name: &str) -> Option<PathBuf> {
+  env_value(env_key).map(PathBuf::from)
+    .or(util::home_dir().map(|home| home.join(".aws").join(name)))
+}
+
+fn read_section(path: Option<PathBuf>, name: &str) -> Option<Vec<(String, String)>> {
+  let contents = fs::read_to_string(path?).ok()?;
+  parse_section(&contents, name)
+}
+
+fn parse_section(contents: &str, name: &str) -> Option<Vec<(String, String)>> {
+  let mut section = None;
+  let mut in_section = false;
+  for line in contents.lines().map(|l| l.trim()) {
+    if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+      continue;
+    }
+    if line.starts_with('[') && line.ends_with(']') {
+      in_section = line[1..line.len() - 1].trim() == name;
+      if in_section && section.is_none() {
+        section = Some(Vec::new());
+      }
+    } else if let (true, Some(idx), Some(values)) = (in_section, line.find('='), section.as_mut()) {
+      values.push((line[..idx].trim().to_lowercase(), line[idx + 1..].trim().to_string()));
+    }
+  }
+  section
+}
+
+fn section_value(section: &[(String, String)], key: &str) -> Option<String> {
+  section.iter().find(|&&(ref k, ref v)| k == key && !v.is_empty()).map(|&(_, ref v)| v.clone())
+}
+
+fn env_value(key: &str) -> Option<String> {
+  env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+// Password managers print the secret on the first line, anything after it is metadata.
+fn run_secret_command(command: &str) -> Result<String> {
+  let output = run_shell_command(command).with_context(|| format!("Failed to run aws.secret_command: {}", command))?;
+  output.lines().next().map(|line| line.trim().to_string()).filter(|line| !line.is_empty())
+    .ok_or(anyhow!("aws.secret_command printed no secret: {}", command))
+}
+
+// credential_process prints a JSON document, see
+// https://docs.aws.amazon.com/cli/latest/userguide/cli-configure-sourcing-external.html
+fn run_credential_process(command: &str) -> Result<Credentials> {
+  let output = run_shell_command(command).with_context(|| format!("Failed to run credential_process: {}", command))?;
+  let body: serde_json::Value = serde_json::from_str(&output).context("credential_process printed invalid JSON")?;
+  let field = |name: &str| body.get(name).and_then(|v| v.as_str()).filter(|v| !v.is_empty()).map(|v| v.to_string());
+  match (field("AccessKeyId"), field("SecretAccessKey")) {
+    (Some(access_key), Some(secret_key)) => Ok(Credentials { access_key: access_key, secret_key: secret_key, session_token: field("SessionToken") }),
+    _ => Err(anyhow!("credential_process output is missing AccessKeyId or SecretAccessKey")),
+  }
+}
+
+fn run_shell_command(command: &str) -> Result<String> {
+  let output = Command::new("sh").arg("-c").arg(command).stdin(Stdio::null()).stderr(Stdio::inherit()).output()?;
+  if !output.status.success() {
+    return Err(anyhow!("Command exited with {}", output.status));
+  }
+  Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testutil;
+  use util;
+
+  const AWS_VARS: &'static [&'static str] = &["AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY", "AWS_SESSION_TOKEN", "AWS_PROFILE",
+                                               "AWS_SHARED_CREDENTIALS_FILE", "AWS_CONFIG_FILE"];
+
+  const CREDENTIALS_FILE: &'static str = "\
+# keys for the default profile
+[default]
+aws_access_key_id = DEFAULTKEY
+aws_secret_access_key = defaultsecret
+
+[work]
+; rotated monthly
+aws_access_key_id=WORKKEY
+aws_secret_access_key = work=secret
+aws_session_token = worktoken
+";
+
+  const CONFIG_FILE: &'static str = "\
+[default]
+region = us-east-1
+
+[profile work]
+aws_access_key_id = IGNOREDKEY
+
+[profile sso]
+credential_process = printf '%s' '{\"Version\": 1, \"AccessKeyId\": \"SSOKEY\", \"SecretAccessKey\": \"ssosecret\", \"SessionToken\": \"ssotoken\"}'
+
+[sso]
+credential_process = exit 1
+";
+
+  fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("drop-credentials-test-{}-{}", util::rand_string(8), name));
+    fs::write(&path, contents).unwrap();
+    path
+  }
+
+  // Runs with the given environment on top of the test credentials and config files.
+  fn with_aws_env<F: FnOnce()>(vars: &[(&str, &str)], f: F) {
+    with_aws_files(CREDENTIALS_FILE, CONFIG_FILE, vars, f)
+  }
+
+  fn with_aws_files<F: FnOnce()>(credentials: &str, config: &str, vars: &[(&str, &str)], f: F) {
+    let credentials_file = temp_file("credentials", credentials);
+    let config_file = temp_file("config", config);
+    let credentials_path = util::path_to_str(&credentials_file);
+    let config_path = util::path_to_str(&config_file);
+    let mut env: Vec<(&str, Option<&str>)> = AWS_VARS.iter()
+      .map(|&name| (name, vars.iter().find(|&&(k, _)| k == name).map(|&(_, v)| v)))
+      .filter(|&(name, _)| name != "AWS_SHARED_CREDENTIALS_FILE" && name != "AWS_CONFIG_FILE")
+      .collect();
+    env.push(("AWS_SHARED_CREDENTIALS_FILE", Some(&credentials_path)));
+    env.push(("AWS_CONFIG_FILE", Some(&config_path)));
+    testutil::with_env(&env, f);
+    fs::remove_file(credentials_file).unwrap();
+    fs::remove_file(config_file).unwrap();
+  }
+
+  fn resolved(config: DropConfig) -> (String, String, Option<String>) {
+    let credentials = resolve(&config).unwrap();
+    (credentials.access_key, credentials.secret_key, credentials.session_token)
+  }
+
+  fn owned(key: &str, secret: &str, token: Option<&str>) -> (String, String, Option<String>) {
+    (key.to_string(), secret.to_string(), token.map(|t| t.to_string()))
+  }
+
+  #[test]
+  fn parses_sections() {
+    let section = parse_section(CREDENTIALS_FILE, "work").unwrap();
+    assert_eq!(section_value(&section, "aws_access_key_id"), Some("WORKKEY".to_string()));
+    assert_eq!(section_value(&section, "aws_secret_access_key"), Some("work=secret".to_string()));
+    assert_eq!(section.len(), 3);
+
+    let section = parse_section("[ default ]\nAWS_Access_Key_ID = KEY\n# aws_secret_access_key = commented\nregion =\n", "default").unwrap();
+    assert_eq!(section_value(&section, "aws_access_key_id"), Some("KEY".to_string()));
+    assert_eq!(section_value(&section, "aws_secret_access_key"), None);
+    assert_eq!(section_value(&section, "region"), None);
+
+    assert!(parse_section(CREDENTIALS_FILE, "profile work").is_none());
+    assert!(parse_section(CONFIG_FILE, "profile work").is_some());
+  }
+
+  #[test]
+  fn prefers_configured_keys_then_environment_then_profiles() {
+    with_aws_env(&[("AWS_ACCESS_KEY_ID", "ENVKEY"), ("AWS_SECRET_ACCESS_KEY", "envsecret"), ("AWS_SESSION_TOKEN", "envtoken")], || {
+      let config = DropConfig::builder().aws_credentials("CONFIGKEY", "configsecret").aws_session_token("configtoken").build().unwrap();
+      assert_eq!(resolved(config), owned("CONFIGKEY", "configsecret", Some("configtoken")));
+
+      let config = DropConfig::builder().aws_session_token("configtoken").aws_profile("work").build().unwrap();
+      assert_eq!(resolved(config), owned("ENVKEY", "envsecret", Some("envtoken")));
+    });
+
+    with_aws_env(&[("AWS_ACCESS_KEY_ID", "ENVKEY")], || {
+      assert_eq!(resolved(DropConfig::builder().build().unwrap()), owned("DEFAULTKEY", "defaultsecret", None));
+    });
+  }
+
+  #[test]
+  fn reads_profiles_from_credentials_and_config_files() {
+    with_aws_env(&[("AWS_PROFILE", "work")], || {
+      assert_eq!(resolved(DropConfig::builder().build().unwrap()), owned("WORKKEY", "work=secret", Some("worktoken")));
+      assert_eq!(resolved(DropConfig::builder().aws_profile("sso").build().unwrap()), owned("SSOKEY", "ssosecret", Some("ssotoken")));
+      assert!(resolve(&DropConfig::builder().aws_profile("missing").build().unwrap()).is_err());
+      assert!(!available(&DropConfig::builder().aws_profile("missing").build().unwrap()));
+      assert!(available(&DropConfig::builder().build().unwrap()));
+    });
+  }
+
+  #[test]
+  fn counts_only_profiles_with_credentials_as_available() {
+    let config = || DropConfig::builder().build().unwrap();
+    with_aws_files("", "[default]\nregion = eu-west-1\n", &[], || {
+      assert!(!available(&config()));
+    });
+    with_aws_files("[default]\naws_access_key_id = KEY\n", "[default]\nregion = eu-west-1\n", &[], || {
+      assert!(!available(&config()));
+    });
+    with_aws_files("[default]\naws_access_key_id = KEY\naws_secret_access_key = secret\n", "", &[], || {
+      assert!(available(&config()));
+    });
+    with_aws_files("", "[default]\nregion = eu-west-1\n", &[("AWS_ACCESS_KEY_ID", "ENVKEY"), ("AWS_SECRET_ACCESS_KEY", "envsecret")], || {
+      assert!(available(&config()));
+    });
+    with_aws_env(&[], || {
+      assert!(available(&DropConfig::builder().aws_profile("sso").build().unwrap()));
+      assert!(available(&DropConfig::builder().aws_profile("work").build().unwrap()));
+    });
+  }
+
+  #[test]
+  fn parses_credential_process_output() {
+    let credentials = run_credential_process("echo '{\"Version\": 1, \"AccessKeyId\": \"KEY\", \"SecretAccessKey\": \"secret\"}'").unwrap();
+    assert_eq!((credentials.access_key.as_str(), credentials.secret_key.as_str(), credentials.session_token), ("KEY", "secret", None));
+
+    assert!(run_credential_process("echo '{\"Version\": 1, \"AccessKeyId\": \"KEY\"}'").is_err());
+    assert!(run_credential_process("echo '{\"AccessKeyId\": \"KEY\", \"SecretAccessKey\": \"\"}'").is_err());
+    assert!(run_credential_process("echo not json").is_err());
+    assert!(run_credential_process("exit 3").is_err());
+  }
+
+  #[test]
+  fn takes_the_first_line_of_secret_commands() {
+    assert_eq!(run_secret_command("printf '  s3cret  \\nusername: drop\\nurl: example.com\\n'").unwrap(), "s3cret");
+    assert_eq!(run_secret_command("echo s3cret").unwrap(), "s3cret");
+    assert!(run_secret_command("printf '\\nsecret on the second line\\n'").is_err());
+    assert!(run_secret_command("true").is_err());
+    assert!(run_secret_command("echo s3cret; exit 1").is_err());
+  }
+}
@@ -0,0 +1,90 @@
+use std::error::Error;
+use std::fmt;
+use anyhow;
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_CONFIG: i32 = 2;
+pub const EXIT_CANCELLED: i32 = 3;
+pub const EXIT_CAPTURE: i32 = 4;
+pub const EXIT_UPLOAD: i32 = 5;
+
+// Failures that callers of drop (scripts, keybinding wrappers) may want to react to differently.
+// These are attached as context to the underlying error so the cause is still reported.
+#[derive(Debug)]
+pub enum DropError {
+  Cancelled,
+  Capture(String),
+  Upload(String),
+  Config(String),
+}
+
+impl DropError {
+  pub fn exit_code(&self) -> i32 {
+    match *self {
+      DropError::Cancelled => EXIT_CANCELLED,
+      DropError::Capture(_) => EXIT_CAPTURE,
+      DropError::Upload(_) => EXIT_UPLOAD,
+      DropError::Config(_) => EXIT_CONFIG,
+    }
+  }
+}
+
+impl fmt::Display for DropError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      DropError::Cancelled => write!(f, "Cancelled drop"),
+      DropError::Capture(ref msg) => write!(f, "{}", msg),
+      DropError::Upload(ref msg) => write!(f, "{}", msg),
+      DropError::Config(ref msg) => write!(f, "{}", msg),
+    }
+  }
+}
+
+impl Error for DropError {}
+
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+  err.downcast_ref::<DropError>()
+    .or_else(|| err.chain().filter_map(|cause| cause.downcast_ref::<DropError>()).next())
+    .map(|drop_err| drop_err.exit_code())
+    .unwrap_or(EXIT_FAILURE)
+}
+
+pub fn is_cancelled(err: &anyhow::Error) -> bool {
+  exit_code(err) == EXIT_CANCELLED
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::Context;
+  use std::io;
+
+  #[test]
+  fn maps_errors_to_exit_codes() {
+    let cases: Vec<(anyhow::Error, i32)> = vec![
+      (DropError::Cancelled.into(), EXIT_CANCELLED),
+      (DropError::Capture("Failed to capture screenshot".to_string()).into(), EXIT_CAPTURE),
+      (DropError::Upload("Failed to upload".to_string()).into(), EXIT_UPLOAD),
+      (DropError::Config("Invalid config".to_string()).into(), EXIT_CONFIG),
+      (anyhow!("Something else went wrong"), EXIT_FAILURE),
+      (io::Error::new(io::ErrorKind::Other, "disk full").into(), EXIT_FAILURE),
+    ];
+    for (err, code) in cases {
+      assert_eq!(exit_code(&err), code, "exit code for {}", err);
+    }
+    assert_eq!(vec![EXIT_FAILURE, EXIT_CONFIG, EXIT_CANCELLED, EXIT_CAPTURE, EXIT_UPLOAD], vec![1, 2, 3, 4, 5]);
+  }
+
+  #[test]
+  fn finds_drop_errors_in_context() {
+    let result: Result<(), io::Error> = Err(io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused"));
+    let err = result.context(DropError::Upload("Failed to upload shot.png".to_string())).unwrap_err();
+    assert_eq!(exit_code(&err), EXIT_UPLOAD);
+    assert_eq!(format!("{:#}", err), "Failed to upload shot.png: connection refused");
+
+    let err = anyhow::Error::from(DropError::Cancelled).context("Screenshot selection");
+    assert_eq!(exit_code(&err), EXIT_CANCELLED);
+    assert!(is_cancelled(&err));
+    assert!(!is_cancelled(&anyhow::Error::from(DropError::Config("Invalid config".to_string())).context("Failed to load config")));
+  }
+}
@@ -0,0 +1,119 @@
+use conf::DropConfig;
+use naming;
+
+// Wraps a drop url in the markup configured with --format, which is what gets copied and printed.
+// Images and videos are embedded where the format supports it, anything else becomes a plain link.
+pub fn format_url(config: &DropConfig, url: &str, key: &str, mime_type: &str) -> String {
+  let name = key.rsplit('/').next().unwrap_or(key);
+  let media = media_kind(mime_type);
+  match config.format.as_ref() {
+    "markdown" => match media {
+      Some(_) => format!("![{}]({})", escape_markdown(name), url),
+      None => format!("[{}]({})", escape_markdown(name), url),
+    },
+    "html" => match media {
+      Some(Media::Image) => format!("<img src=\"{}\" alt=\"{}\">", escape_html(url), escape_html(name)),
+      Some(Media::Video) => format!("<video src=\"{}\" controls></video>", escape_html(url)),
+      None => format!("<a href=\"{}\">{}</a>", escape_html(url), escape_html(name)),
+    },
+    // Neither bbcode nor org can escape brackets, so they're percent encoded in urls and replaced in names
+    "bbcode" => match media {
+      Some(Media::Image) => format!("[img]{}[/img]", encode_brackets(url)),
+      Some(Media::Video) => format!("[video]{}[/video]", encode_brackets(url)),
+      None => format!("[url={}]{}[/url]", encode_brackets(url), naming::sanitize(name)),
+    },
+    // Org only shows links without a description inline
+    "org" => match media {
+      Some(_) => format!("[[{}]]", encode_brackets(url)),
+      None => format!("[[{}][{}]]", encode_brackets(url), naming::sanitize(name)),
+    },
+    "custom" => config.format_template.clone().unwrap_or_default()
+      .replace("{url}", url)
+      .replace("{name}", name)
+      .replace("{key}", key)
+      .replace("{mime}", mime_type),
+    _ => url.to_string(),
+  }
+}
+
+enum Media {
+  Image,
+  Video,
+}
+
+fn media_kind(mime_type: &str) -> Option<Media> {
+  if mime_type.starts_with("image/") {
+    Some(Media::Image)
+  } else if mime_type.starts_with("video/") {
+    Some(Media::Video)
+  } else {
+    None
+  }
+}
+
+fn escape_markdown(value: &str) -> String {
+  value.replace('[', "\\[").replace(']', "\\]")
+}
+
+fn escape_html(value: &str) -> String {
+  value.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn encode_brackets(url: &str) -> String {
+  url.replace('[', "%5B").replace(']', "%5D")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use util;
+  use std::env;
+
+  fn config(format: &str) -> DropConfig {
+    let builder = DropConfig::builder().dir(util::path_to_str(&env::temp_dir())).format(format);
+    if format == "custom" { builder.format_template("{name} ({mime}) {key} -> {url}") } else { builder }.build().unwrap()
+  }
+
+  #[test]
+  fn formats_urls() {
+    let url = "https://example.com/2024/shot.png";
+    let cases = vec![
+      ("raw", "image/png", "https://example.com/2024/shot.png"),
+      ("raw", "application/pdf", "https://example.com/2024/shot.png"),
+      ("markdown", "image/png", "![shot.png](https://example.com/2024/shot.png)"),
+      ("markdown", "video/mp4", "![shot.png](https://example.com/2024/shot.png)"),
+      ("markdown", "application/pdf", "[shot.png](https://example.com/2024/shot.png)"),
+      ("html", "image/png", "<img src=\"https://example.com/2024/shot.png\" alt=\"shot.png\">"),
+      ("html", "video/mp4", "<video src=\"https://example.com/2024/shot.png\" controls></video>"),
+      ("html", "application/pdf", "<a href=\"https://example.com/2024/shot.png\">shot.png</a>"),
+      ("bbcode", "image/png", "[img]https://example.com/2024/shot.png[/img]"),
+      ("bbcode", "video/mp4", "[video]https://example.com/2024/shot.png[/video]"),
+      ("bbcode", "application/pdf", "[url=https://example.com/2024/shot.png]shot.png[/url]"),
+      ("org", "image/png", "[[https://example.com/2024/shot.png]]"),
+      ("org", "video/mp4", "[[https://example.com/2024/shot.png]]"),
+      ("org", "application/pdf", "[[https://example.com/2024/shot.png][shot.png]]"),
+      ("custom", "image/png", "shot.png (image/png) 2024/shot.png -> https://example.com/2024/shot.png"),
+      ("custom", "video/mp4", "shot.png (video/mp4) 2024/shot.png -> https://example.com/2024/shot.png"),
+      ("custom", "application/pdf", "shot.png (application/pdf) 2024/shot.png -> https://example.com/2024/shot.png"),
+    ];
+    for (format, mime_type, expected) in cases {
+      assert_eq!(format_url(&config(format), url, "2024/shot.png", mime_type), expected, "{} for {}", format, mime_type);
+    }
+  }
+
+  #[test]
+  fn escapes_names_and_urls() {
+    let key = "a]b[url] <i>&.txt";
+    let url = "https://example.com/a]b[url]%20<i>&.txt";
+    let cases = vec![
+      ("markdown", "[a\\]b\\[url\\] <i>&.txt](https://example.com/a]b[url]%20<i>&.txt)"),
+      ("html", "<a href=\"https://example.com/a]b[url]%20&lt;i&gt;&amp;.txt\">a]b[url] &lt;i&gt;&amp;.txt</a>"),
+      ("bbcode", "[url=https://example.com/a%5Db%5Burl%5D%20<i>&.txt]a-b-url-i-.txt[/url]"),
+      ("org", "[[https://example.com/a%5Db%5Burl%5D%20<i>&.txt][a-b-url-i-.txt]]"),
+    ];
+    for (format, expected) in cases {
+      assert_eq!(format_url(&config(format), url, key, "text/plain"), expected, "{}", format);
+    }
+    assert_eq!(format_url(&config("org"), "https://example.com/x]].png", "x]].png", "image/png"), "[[https://example.com/x%5D%5D.png]]");
+  }
+}
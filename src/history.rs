@@ -0,0 +1,288 @@
+use conf::DropConfig;
+use upload::Upload;
+use mime;
+use util;
+
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local, Utc};
+use serde_json;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+  pub id: u64,
+  pub local_path: String,
+  pub key: String,
+  pub url: String,
+  pub size: u64,
+  pub mime_type: String,
+  pub kind: String,
+  pub backend: String,
+  #[serde(default)]
+  pub profile: Option<String>,
+  pub timestamp: DateTime<Utc>,
+  #[serde(default)]
+  pub expires_at: Option<DateTime<Utc>>,
+  #[serde(default)]
+  pub deleted: bool,
+}
+
+impl HistoryEntry {
+  pub fn summary(&self) -> String {
+    format!("{:>5}  {}  {:<10}  {}{}", self.id, self.timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M"), self.kind, self.url,
+            if self.deleted { " (deleted)" } else { "" })
+  }
+
+  pub fn details(&self) -> String {
+    format!("id:         {}\nurl:        {}\nkey:        {}\nlocal path: {}\nsize:       {}\ntype:       {}\nkind:       {}\nbackend:    {}\nprofile:    {}\ntimestamp:  {}\nexpires:    {}\ndeleted:    {}",
+            self.id, self.url, self.key, self.local_path, self.size, self.mime_type, self.kind, self.backend,
+            self.profile.clone().unwrap_or("none".to_string()),
+            self.timestamp.with_timezone(&Local).to_rfc3339(),
+            self.expires_at.map(|e| e.with_timezone(&Local).to_rfc3339()).unwrap_or("never".to_string()),
+            if self.deleted { "yes" } else { "no" })
+  }
+
+  // Drops without a profile were uploaded with the top level config, which no profile can stand in for.
+  pub fn uploaded_with_profile(&self, profile: &Option<String>) -> bool {
+    match (&self.profile, profile) {
+      (&Some(ref a), &Some(ref b)) => a.eq_ignore_ascii_case(b),
+      (&None, &None) => true,
+      _ => false,
+    }
+  }
+
+  pub fn is_expired(&self, now: &DateTime<Utc>) -> bool {
+    self.expires_at.map(|expires_at| expires_at <= *now).unwrap_or(false)
+  }
+
+  fn matches(&self, query: &str) -> bool {
+    let query = query.to_lowercase();
+    [&self.url, &self.key, &self.local_path, &self.kind, &self.mime_type].iter()
+      .any(|value| value.to_lowercase().contains(&query))
+  }
+}
+
+// History is kept as one JSON entry per line so that recording a drop is a single append. The ttl is
+// the one the upload was stored with, which may differ from the configured one.
+pub fn record(config: &DropConfig, file: &Path, upload: &Upload, kind: &str, backend: &str, ttl: Option<u64>) -> Result<HistoryEntry> {
+  let timestamp = Utc::now();
+  let entry = HistoryEntry {
+    id: load(config)?.last().map(|e| e.id + 1).unwrap_or(1),
+    local_path: util::path_to_str(&file.canonicalize().unwrap_or(file.to_path_buf())),
+    key: upload.key.clone(),
+    url: upload.url.clone(),
+    size: file.metadata()?.len(),
+    mime_type: mime::detect_mime_type(file),
+    kind: kind.to_string(),
+    backend: backend.to_string(),
+    profile: config.profile.clone(),
+    timestamp: timestamp,
+    expires_at: ttl.map(|ttl| timestamp + Duration::seconds(ttl as i64)),
+    deleted: false,
+  };
+
+  let mut history_file = OpenOptions::new().create(true).append(true).open(history_file_path(config))?;
+  writeln!(history_file, "{}", serde_json::to_string(&entry)?)?;
+  Ok(entry)
+}
+
+pub fn load(config: &DropConfig) -> Result<Vec<HistoryEntry>> {
+  let path = history_file_path(config);
+  if !path.exists() {
+    return Ok(Vec::new());
+  }
+
+  let mut entries = Vec::new();
+  for line in BufReader::new(File::open(path)?).lines() {
+    let line = line?;
+    if !line.trim().is_empty() {
+      entries.push(serde_json::from_str(&line)?);
+    }
+  }
+  Ok(entries)
+}
+
+// Rewrites the whole history, going through a temporary file so a failure can't truncate it.
+pub fn save(config: &DropConfig, entries: &[HistoryEntry]) -> Result<()> {
+  let path = history_file_path(config);
+  let tmp_path = path.with_extension("tmp");
+  {
+    let mut tmp_file = File::create(&tmp_path)?;
+    for entry in entries {
+      writeln!(tmp_file, "{}", serde_json::to_string(entry)?)?;
+    }
+  }
+  fs::rename(tmp_path, path)?;
+  Ok(())
+}
+
+pub fn mark_deleted(config: &DropConfig, id: u64) -> Result<()> {
+  let mut entries = load(config)?;
+  for entry in entries.iter_mut().filter(|e| e.id == id) {
+    entry.deleted = true;
+  }
+  save(config, &entries)
+}
+
+pub fn last(config: &DropConfig) -> Result<Option<HistoryEntry>> {
+  Ok(load(config)?.pop())
+}
+
+pub fn find(config: &DropConfig, id: u64) -> Result<Option<HistoryEntry>> {
+  Ok(load(config)?.into_iter().find(|e| e.id == id))
+}
+
+// Finds a drop by history id, url or remote key, preferring the most recent match.
+pub fn resolve(config: &DropConfig, target: &str) -> Result<Option<HistoryEntry>> {
+  let entries = load(config)?;
+  if let Ok(id) = target.parse::<u64>() {
+    if let Some(entry) = entries.iter().find(|e| e.id == id) {
+      return Ok(Some(entry.clone()));
+    }
+  }
+  Ok(entries.into_iter().rev().find(|e| e.url == target || e.key == target))
+}
+
+// Deduplicated drops share one remote object, which has to stay until none of them are left.
+pub fn sharing_entries(entries: &[HistoryEntry], entry: &HistoryEntry) -> Vec<u64> {
+  entries.iter()
+    .filter(|e| !e.deleted && e.id != entry.id && e.key == entry.key && e.backend == entry.backend && e.profile == entry.profile)
+    .map(|e| e.id)
+    .collect()
+}
+
+pub fn search(config: &DropConfig, query: &str) -> Result<Vec<HistoryEntry>> {
+  Ok(load(config)?.into_iter().filter(|e| e.matches(query)).collect())
+}
+
+fn history_file_path(config: &DropConfig) -> PathBuf {
+  Path::new(&config.dir).join(".history")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::fs;
+
+  fn entry(id: u64, key: &str, backend: &str, profile: Option<&str>, deleted: bool) -> HistoryEntry {
+    HistoryEntry {
+      id: id,
+      local_path: format!("/tmp/{}", key),
+      key: key.to_string(),
+      url: format!("https://example.com/{}", key),
+      size: 5,
+      mime_type: "text/plain".to_string(),
+      kind: "file".to_string(),
+      backend: backend.to_string(),
+      profile: profile.map(|p| p.to_string()),
+      timestamp: Utc::now(),
+      expires_at: None,
+      deleted: deleted,
+    }
+  }
+
+  fn temp_config() -> DropConfig {
+    let dir = env::temp_dir().join(format!("drop-history-test-{}", util::rand_string(8)));
+    DropConfig::builder().dir(util::path_to_str(&dir)).build().unwrap()
+  }
+
+  fn record_file(config: &DropConfig, name: &str, contents: &str, key: &str, ttl: Option<u64>) -> HistoryEntry {
+    let file = Path::new(&config.dir).join(name);
+    fs::write(&file, contents).unwrap();
+    let upload = Upload { key: key.to_string(), url: format!("https://example.com/{}", key) };
+    record(config, &file, &upload, "file", "s3", ttl).unwrap()
+  }
+
+  #[test]
+  fn records_and_loads_drops() {
+    let config = temp_config();
+    assert!(load(&config).unwrap().is_empty());
+    assert!(last(&config).unwrap().is_none());
+
+    let first = record_file(&config, "notes.txt", "hello", "abc--notes.txt", None);
+    let second = record_file(&config, "shot.png", "\u{89}PNG", "def.png", Some(3600));
+    assert_eq!((first.id, second.id), (1, 2));
+
+    let entries = load(&config).unwrap();
+    assert_eq!(entries.len(), 2);
+    let loaded = &entries[0];
+    assert_eq!((loaded.key.as_str(), loaded.url.as_str(), loaded.size, loaded.kind.as_str(), loaded.backend.as_str()),
+               ("abc--notes.txt", "https://example.com/abc--notes.txt", 5, "file", "s3"));
+    assert_eq!(loaded.mime_type, "text/plain; charset=utf-8");
+    assert!(loaded.local_path.ends_with("notes.txt"));
+    assert_eq!(loaded.timestamp, first.timestamp);
+    assert!(loaded.expires_at.is_none() && !loaded.deleted);
+    assert_eq!(entries[1].expires_at, Some(second.timestamp + Duration::seconds(3600)));
+    assert_eq!(last(&config).unwrap().map(|e| e.id), Some(2));
+    assert_eq!(find(&config, 1).unwrap().map(|e| e.key), Some("abc--notes.txt".to_string()));
+    assert!(find(&config, 3).unwrap().is_none());
+
+    mark_deleted(&config, 1).unwrap();
+    assert!(find(&config, 1).unwrap().unwrap().deleted);
+    assert!(!find(&config, 2).unwrap().unwrap().deleted);
+    assert_eq!(record_file(&config, "again.txt", "again", "ghi.txt", None).id, 3);
+    fs::remove_dir_all(&config.dir).unwrap();
+  }
+
+  #[test]
+  fn searches_drops() {
+    let config = temp_config();
+    record_file(&config, "Quarterly Report.txt", "report", "q3-report.txt", None);
+    record_file(&config, "shot.png", "png", "xyz.png", None);
+    let ids = |query: &str| search(&config, query).unwrap().iter().map(|e| e.id).collect::<Vec<u64>>();
+    assert_eq!(ids("REPORT"), vec![1]);
+    assert_eq!(ids("example.com"), vec![1, 2]);
+    assert_eq!(ids("xyz"), vec![2]);
+    assert_eq!(ids("file"), vec![1, 2]);
+    assert!(ids("missing").is_empty());
+    fs::remove_dir_all(&config.dir).unwrap();
+  }
+
+  #[test]
+  fn resolves_drops_by_id_key_or_url() {
+    let config = temp_config();
+    record_file(&config, "a.txt", "a", "shared.txt", None);
+    record_file(&config, "b.txt", "b", "shared.txt", None);
+    record_file(&config, "c.txt", "c", "1", None);
+    let resolved = |target: &str| resolve(&config, target).unwrap().map(|e| e.id);
+    assert_eq!(resolved("1"), Some(1));
+    assert_eq!(resolved("3"), Some(3));
+    assert_eq!(resolved("shared.txt"), Some(2));
+    assert_eq!(resolved("https://example.com/shared.txt"), Some(2));
+    assert_eq!(resolved("https://example.com/1"), Some(3));
+    assert_eq!(resolved("42"), None);
+    assert_eq!(resolved("https://example.com/missing.txt"), None);
+    fs::remove_dir_all(&config.dir).unwrap();
+  }
+
+  #[test]
+  fn finds_entries_sharing_an_object() {
+    let entries = vec![
+      entry(1, "abc.txt", "s3", None, false),
+      entry(2, "abc.txt", "s3", None, false),
+      entry(3, "abc.txt", "s3", None, true),
+      entry(4, "abc.txt", "sftp", None, false),
+      entry(5, "abc.txt", "s3", Some("docs"), false),
+      entry(6, "def.txt", "s3", None, false),
+    ];
+    assert_eq!(sharing_entries(&entries, &entries[0]), vec![2]);
+    assert_eq!(sharing_entries(&entries, &entries[1]), vec![1]);
+    assert!(sharing_entries(&entries, &entries[3]).is_empty());
+    assert!(sharing_entries(&entries, &entries[4]).is_empty());
+    assert!(sharing_entries(&entries, &entries[5]).is_empty());
+  }
+
+  #[test]
+  fn matches_profiles() {
+    let docs = Some("docs".to_string());
+    assert!(entry(1, "a", "s3", None, false).uploaded_with_profile(&None));
+    assert!(!entry(1, "a", "s3", None, false).uploaded_with_profile(&docs));
+    assert!(!entry(1, "a", "s3", Some("docs"), false).uploaded_with_profile(&None));
+    assert!(entry(1, "a", "s3", Some("Docs"), false).uploaded_with_profile(&docs));
+    assert!(!entry(1, "a", "s3", Some("evidence"), false).uploaded_with_profile(&docs));
+  }
+}
@@ -0,0 +1,157 @@
+use conf::DropConfig;
+use mime;
+use upload::{Upload, Uploader};
+use util;
+
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use anyhow::Result;
+use serde_json;
+use ureq;
+
+// Posts files as multipart form data to paste style services (0x0.st, self-hosted file drops, etc)
+// and takes the share url from their response.
+pub struct HttpUploader<'a> {
+  config: &'a DropConfig,
+  url: String,
+}
+
+impl<'a> HttpUploader<'a> {
+  pub fn new(config: &'a DropConfig) -> Result<HttpUploader<'a>> {
+    let url = config.http_url.clone().ok_or(anyhow!("HTTP backend requires http.url to be configured"))?;
+    Ok(HttpUploader { config: config, url: url })
+  }
+
+  fn extract_url(&self, response: ureq::Response) -> Result<String> {
+    let source = self.config.http_response_url.clone();
+    let url =
+      if source.starts_with("header:") {
+        let header = &source["header:".len()..];
+        response.header(header).map(|h| h.to_string())
+          .ok_or(anyhow!("HTTP upload response is missing the {} header", header))?
+      } else if source.starts_with("json:") {
+        let pointer = &source["json:".len()..];
+        let body: serde_json::Value = serde_json::from_str(&response.into_string()?)?;
+        body.pointer(pointer).and_then(|v| v.as_str()).map(|v| v.to_string())
+          .ok_or(anyhow!("HTTP upload response has no string at json pointer {}", pointer))?
+      } else {
+        response.into_string()?.trim().to_string()
+      };
+
+    if url.is_empty() {
+      Err(anyhow!("HTTP upload response did not contain a url"))
+    } else {
+      Ok(url)
+    }
+  }
+}
+
+impl<'a> Uploader for HttpUploader<'a> {
+  fn name(&self) -> &'static str {
+    "http"
+  }
+
+  fn upload(&self, file_path: &Path, key: &str) -> Result<Upload> {
+    let boundary = format!("drop-{}", util::rand_string(24));
+    let mut prefix = String::new();
+    for &(ref name, ref value) in &self.config.http_fields {
+      prefix.push_str(&format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value));
+    }
+    prefix.push_str(&format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                             boundary, self.config.http_field, key.replace('"', ""), mime::detect_mime_type(file_path)));
+    let suffix = format!("\r\n--{}--\r\n", boundary);
+
+    let length = prefix.len() as u64 + file_path.metadata()?.len() + suffix.len() as u64;
+    let body = Cursor::new(prefix.into_bytes()).chain(File::open(file_path)?).chain(Cursor::new(suffix.into_bytes()));
+
+    // Redirects aren't followed so that a Location header can be used as the share url
+    let agent = ureq::AgentBuilder::new().redirects(0).build();
+    let request = self.config.http_headers.iter()
+      .fold(agent.post(&self.url), |req, &(ref k, ref v)| req.set(k, v))
+      .set("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
+      .set("Content-Length", &length.to_string());
+
+    let response = request.send(body).map_err(|err| anyhow!("HTTP upload to {} failed: {}", self.url, err))?;
+    Ok(Upload { url: self.extract_url(response)?, key: key.to_string() })
+  }
+
+  // Urls normally come from the upload response, this is only a best effort for existing drops.
+  fn produce_url(&self, key: &str) -> String {
+    if self.config.host.is_some() || self.config.url_template.is_some() {
+      util::create_drop_url(self.config, key.to_string())
+    } else {
+      format!("{}/{}", self.url.trim_end_matches('/'), key)
+    }
+  }
+
+  fn delete(&self, _key: &str) -> Result<()> {
+    Err(anyhow!("The HTTP backend does not support deleting uploads"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testutil::{self, Response};
+  use std::env;
+  use std::fs;
+  use std::path::PathBuf;
+
+  fn config(url: &str, response_url: &str) -> DropConfig {
+    DropConfig::builder().dir(env::temp_dir().to_string_lossy().into_owned()).backend("http")
+      .http_url(url).http_response_url(response_url).http_form_field("expires", "24").http_header("Authorization", "Bearer token")
+      .build().unwrap()
+  }
+
+  fn upload(response_url: &str, response: Response) -> (Result<Upload>, testutil::Request) {
+    let file: PathBuf = env::temp_dir().join(format!("drop-http-test-{}.txt", util::rand_string(8)));
+    fs::write(&file, "hello").unwrap();
+    let (url, server) = testutil::serve(vec![response]);
+    let config = config(&url, response_url);
+    let result = HttpUploader::new(&config).unwrap().upload(&file, "note.txt");
+    fs::remove_file(file).unwrap();
+    (result, server.join().unwrap().remove(0))
+  }
+
+  #[test]
+  fn posts_multipart_form_data() {
+    let (result, request) = upload("body", Response::new(200, "https://paste.example.com/abc\n"));
+    assert_eq!(result.unwrap().url, "https://paste.example.com/abc");
+
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.header("authorization"), Some("Bearer token"));
+    assert!(request.header("content-type").unwrap().starts_with("multipart/form-data; boundary=drop-"));
+    let body = String::from_utf8(request.body).unwrap();
+    assert!(body.contains("Content-Disposition: form-data; name=\"expires\"\r\n\r\n24\r\n"));
+    assert!(body.contains("Content-Disposition: form-data; name=\"file\"; filename=\"note.txt\"\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nhello\r\n"));
+  }
+
+  #[test]
+  fn takes_urls_from_json_responses() {
+    let (result, _) = upload("json:/files/0/url", Response::new(200, r#"{"files": [{"url": "https://files.example.com/x"}]}"#));
+    assert_eq!(result.unwrap().url, "https://files.example.com/x");
+
+    let (result, _) = upload("json:/missing", Response::new(200, r#"{"url": "https://files.example.com/x"}"#));
+    assert!(result.unwrap_err().to_string().contains("no string at json pointer /missing"));
+  }
+
+  #[test]
+  fn takes_urls_from_headers() {
+    let (result, _) = upload("header:Location", Response::new(302, "").with_header("Location", "https://files.example.com/y"));
+    assert_eq!(result.unwrap().url, "https://files.example.com/y");
+  }
+
+  #[test]
+  fn reports_failed_uploads() {
+    let (result, _) = upload("body", Response::new(500, "out of space"));
+    assert!(result.unwrap_err().to_string().contains("500"));
+  }
+
+  #[test]
+  fn rejects_unknown_response_url_sources() {
+    let result = DropConfig::builder().dir(env::temp_dir().to_string_lossy().into_owned()).backend("http")
+      .http_url("https://paste.example.com").http_response_url("location").build();
+    assert!(result.is_err());
+  }
+}
@@ -0,0 +1,58 @@
+extern crate config;
+extern crate rand;
+extern crate clap;
+extern crate nix;
+extern crate sys_info;
+extern crate flate2;
+extern crate tar;
+extern crate ureq;
+extern crate chrono;
+extern crate hmac;
+extern crate sha2;
+extern crate hex;
+extern crate md5;
+extern crate base64;
+extern crate serde;
+extern crate serde_json;
+extern crate ssh2;
+
+#[macro_use]
+extern crate anyhow;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate serde_derive;
+
+#[cfg(target_os = "linux")]
+extern crate gtk;
+#[cfg(target_os = "linux")]
+extern crate libappindicator;
+#[cfg(target_os = "linux")]
+extern crate libc;
+
+pub mod aws;
+mod credentials;
+mod multipart;
+mod sigv4;
+mod sftp;
+pub mod clip;
+pub mod conf;
+pub mod notify;
+pub mod capture;
+pub mod util;
+mod http;
+pub mod mime;
+pub mod history;
+pub mod retention;
+mod ui;
+pub mod upload;
+pub mod report;
+pub mod error;
+pub mod format;
+pub mod naming;
+#[cfg(test)]
+mod testutil;
+
+pub use conf::{DropConfig, DropConfigBuilder};
+pub use error::DropError;
+pub use upload::{Upload, Uploader};
@@ -1,163 +1,384 @@
-extern crate config;
-extern crate rand;
+extern crate drop;
 extern crate clap;
-extern crate nix;
-extern crate sys_info;
-extern crate anyhow;
-extern crate flate2;
-extern crate tar;
-
+extern crate chrono;
 #[macro_use]
-extern crate lazy_static;
-
-#[cfg(target_os = "linux")]
-extern crate gtk;
-#[cfg(target_os = "linux")]
-extern crate libappindicator;
-#[cfg(target_os = "linux")]
-extern crate libc;
+extern crate anyhow;
 
 extern { }
 
-use std::path::{PathBuf, Path};
+use std::path::Path;
 use clap::ArgMatches;
 use std::io::{self, Read, Write};
 use std::fs::File;
-use flate2::Compression;
-use flate2::write::GzEncoder;
-
-mod aws;
-mod clip;
-mod conf;
-mod notify;
-mod capture;
-mod util;
-mod cli;
-mod ui;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
 
-use conf::DropConfig;
+use drop::{aws, capture, clip, conf, error, format, history, naming, notify, retention, upload, util};
+use drop::{DropConfig, DropError};
+use drop::report::DropReport;
+
+mod cli;
 
 fn main() {
+  if let Err(err) = run() {
+    if error::is_cancelled(&err) {
+      eprintln!("Cancelled drop, exiting");
+    } else {
+      eprintln!("ERROR: {:#}", err);
+    }
+    std::process::exit(error::exit_code(&err));
+  }
+}
 
+fn run() -> Result<()> {
   let mut cli_app = cli::create_drop_cli_app();
   let matches = cli_app.clone().get_matches();
-  let config = conf::load_config(&matches);
+  if let ("config", Some(config_matches)) = matches.subcommand() {
+    return handle_config(&matches, config_matches);
+  }
+  let config = conf::load_config(&matches)?;
 
-  if matches.is_present("file") {
-    handle_file(config, &matches);
+  if let ("history", Some(history_matches)) = matches.subcommand() {
+    handle_history(config, history_matches)
+  } else if matches.subcommand_matches("last").is_some() {
+    handle_last(config)
+  } else if let ("delete", Some(delete_matches)) = matches.subcommand() {
+    handle_delete(config, delete_matches)
+  } else if let ("gc", Some(gc_matches)) = matches.subcommand() {
+    handle_gc(config, gc_matches)
+  } else if let ("clean", Some(clean_matches)) = matches.subcommand() {
+    handle_clean(config, clean_matches)
+  } else if matches.is_present("file") {
+    handle_file(config, &matches)
   } else if matches.is_present("screenshot") || matches.is_present("video") {
-    handle_screen_capture(config, &matches);
+    handle_screen_capture(config, &matches)
   } else {
     let result = cli_app.print_help();
     if result.is_err() {
-      println!("WARNING: Error occurred attempting to print help text")
+      eprintln!("WARNING: Error occurred attempting to print help text")
     }
+    Ok(())
   }
 }
 
-fn handle_screen_capture(config: DropConfig, matches: &ArgMatches) {
+fn handle_screen_capture(config: DropConfig, matches: &ArgMatches) -> Result<()> {
+  let started = Instant::now();
   let out_file =
     if matches.is_present("video") {
-      capture_screencast(&config)
+      capture::capture_screencast(&config)?
     } else {
-      capture_screenshot(&config)
+      capture::capture_screenshot(&config)?
     };
 
-  let url = handle_upload_and_produce_url(&config, &out_file.as_path(), None);
-  clip::copy_to_clipboard(url.clone());
+  let capture_time = started.elapsed();
+
+  let kind = if matches.is_present("video") { "screencast" } else { "screenshot" };
+  let report = handle_upload(&config, &out_file.as_path(), None, None, kind, started, Some(capture_time))?;
+  clip::copy_to_clipboard(format_report(&config, &report));
   if config.notifications {
     notify::send_screenshot_notification(&out_file.as_path(), &config);
   }
-  println!("{}", url);
-}
-
-fn capture_screenshot(config: &DropConfig) -> PathBuf {
-  let out_file_name = util::generate_filename(config, None, Some("png".to_string()));
-  let out_file = Path::new(&config.dir).join(out_file_name);
-  capture::screenshot(out_file.as_path(), config);
-  out_file
-}
-
-fn capture_screencast(config: &DropConfig) -> PathBuf {
-  let out_file_name = util::generate_filename(config, None, Some(config.video_format.clone()));
-  let out_file = Path::new(&config.dir).join(out_file_name);
-  capture::screencast(out_file.as_path(), config);
-  out_file
+  print_report(&config, &report)
 }
 
-fn handle_file(config: DropConfig, matches: &ArgMatches) {
+fn handle_file(config: DropConfig, matches: &ArgMatches) -> Result<()> {
+  let started = Instant::now();
   let file = matches.value_of("file").unwrap();
   if file == "-" {
-    handle_stdin(config);
+    handle_stdin(config, started)
   } else {
     let path = Path::new(file);
     if path.is_dir() {
-      let archive = archive_directory(&path);
-      handle_file_upload(config, &archive.as_path())
+      let archive = util::archive_directory(&path)?;
+      handle_file_upload(config, &archive.as_path(), started)
     } else {
-      handle_file_upload(config, &path);
+      handle_file_upload(config, &path, started)
     }
   }
 }
 
-fn handle_file_upload(config: DropConfig, file: &Path) {
+fn handle_file_upload(config: DropConfig, file: &Path, started: Instant) -> Result<()> {
   if !file.exists() {
-    println!("File does not exist! ({:?})", file);
-    std::process::exit(1);
-  } else {
-    let filename = util::generate_filename(&config, file.file_name().map(|s| util::from_os_str(s)), None);
-    let url = handle_upload_and_produce_url(&config, &file, Some(filename.clone()));
-    clip::copy_to_clipboard(url.clone());
-    if config.notifications {
-      notify::send_upload_notification(filename, &config);
-    }
-    println!("{}", url);
+    return Err(anyhow!("File does not exist! ({:?})", file));
   }
-}
 
-fn archive_directory(file: &Path) -> PathBuf {
-  let archive_path = file.with_extension("tar.gz");
-  let archive_file = File::create(&archive_path).unwrap();
-  let enc = GzEncoder::new(archive_file, Compression::default());
-  let mut tar = tar::Builder::new(enc);
-  let file_name = file.file_name().map(|s| util::from_os_str(s)).unwrap_or("archive".to_string());
-  tar.append_dir_all(file_name, file).unwrap();
-  tar.finish().unwrap();
-  archive_path
+  let original = file.file_name().map(|s| util::from_os_str(s));
+  let filename = util::generate_filename(&config, original.clone(), None);
+  let report = handle_upload(&config, &file, Some(filename), original, "file", started, None)?;
+  clip::copy_to_clipboard(format_report(&config, &report));
+  if config.notifications {
+    notify::send_upload_notification(report.key.clone(), &config);
+  }
+  print_report(&config, &report)
 }
 
-fn handle_stdin(config: DropConfig) {
+fn handle_stdin(config: DropConfig, started: Instant) -> Result<()> {
   let mut buffer = Vec::new();
-
-  let result = io::stdin().read_to_end(&mut buffer);
-  if result.is_err() {
-    println!("ERROR: Caught error while reading input from stdin");
-    std::process::exit(1);
-  }
+  io::stdin().read_to_end(&mut buffer).context("Caught error while reading input from stdin")?;
 
   let out_filename = util::generate_filename(&config, None, None);
   let path = Path::new(&config.dir).join(out_filename.clone());
-  let mut file = File::create(&path).unwrap();
+  File::create(&path).and_then(|mut file| file.write_all(&buffer))
+    .context("Caught error while writing to file")?;
 
-  let write_result = file.write_all(&buffer);
-  if write_result.is_err() {
-    println!("ERROR: Caught error while writing to file");
-    std::process::exit(1)
+  let report = handle_upload(&config, &path, Some(out_filename), None, "file", started, None)?;
+  clip::copy_to_clipboard(format_report(&config, &report));
+  if config.notifications {
+    notify::send_upload_notification(report.key.clone(), &config);
   }
+  print_report(&config, &report)
+}
 
-  let url = handle_upload_and_produce_url(&config, &path, Some(out_filename.clone()));
-  clip::copy_to_clipboard(url.clone());
-  if config.notifications {
-    notify::send_upload_notification(out_filename.clone(), &config);
+fn handle_upload(config: &DropConfig, file: &Path, filename: Option<String>, original: Option<String>, kind: &str,
+                 started: Instant, capture_time: Option<Duration>) -> Result<DropReport> {
+  let key = naming::generate_key(config, file, filename, original, kind)?;
+  let report = upload::upload_file(config, file, &key, kind)?;
+  Ok(report.with_timing(capture_time, started.elapsed()))
+}
+
+// Only the result of a drop goes to stdout, everything else is logged to stderr so it can be piped.
+fn print_report(config: &DropConfig, report: &DropReport) -> Result<()> {
+  if config.output == "json" {
+    println!("{}", report.to_json()?);
+  } else {
+    println!("{}", format_report(config, report));
+  }
+  Ok(())
+}
+
+fn format_report(config: &DropConfig, report: &DropReport) -> String {
+  format::format_url(config, &report.url, &report.key, &report.mime_type)
+}
+
+fn handle_history(config: DropConfig, matches: &ArgMatches) -> Result<()> {
+  match matches.subcommand() {
+    ("search", Some(search_matches)) => {
+      let entries = history::search(&config, search_matches.value_of("query").unwrap()).context("Failed to read drop history")?;
+      print_history_entries(&entries);
+    },
+    ("show", Some(show_matches)) => {
+      let id = show_matches.value_of("id").unwrap().parse::<u64>().unwrap_or(0);
+      let entry = history::find(&config, id).context("Failed to read drop history")?
+        .ok_or(anyhow!("No drop found in history with id {}", id))?;
+      println!("{}", entry.details());
+    },
+    (_, list_matches) => {
+      let count = list_matches.and_then(|m| m.value_of("count")).and_then(|c| c.parse::<usize>().ok()).unwrap_or(20);
+      let entries = history::load(&config).context("Failed to read drop history")?;
+      let skip = entries.len().saturating_sub(count);
+      print_history_entries(&entries[skip..]);
+    },
+  }
+  Ok(())
+}
+
+// Handled before the config is loaded, so that a broken or missing config file can be checked and
+// recreated.
+fn handle_config(matches: &ArgMatches, config_matches: &ArgMatches) -> Result<()> {
+  let conf_file = conf::config_file_path(matches)?;
+  match config_matches.subcommand() {
+    ("init", Some(init_matches)) => {
+      conf::init_config_file(&conf_file, init_matches.is_present("force"))?;
+      println!("Wrote default config to {}", conf_file.display());
+    },
+    ("check", _) => {
+      let issues = conf::check_config_file(&conf_file)?;
+      for issue in &issues {
+        println!("{}", issue);
+      }
+      if !issues.is_empty() {
+        return Err(DropError::Config(format!("Found {} {} in {}", issues.len(), if issues.len() == 1 { "problem" } else { "problems" },
+                                             conf_file.display())).into());
+      }
+      conf::load_config(matches)?;
+      println!("{} is valid", conf_file.display());
+    },
+    _ => print!("{}", conf::render_config(&conf::load_config(matches)?)),
+  }
+  Ok(())
+}
+
+fn print_history_entries(entries: &[history::HistoryEntry]) {
+  for entry in entries {
+    println!("{}", entry.summary());
   }
-  println!("{}", url);
 }
 
-fn handle_upload_and_produce_url(config: &DropConfig, file: &Path, filename: Option<String>) -> String {
-  if config.local || config.aws_bucket.is_none() || config.aws_key.is_none() || config.aws_secret.is_none() {
-    format!("file://{}", util::path_to_str(file.canonicalize().unwrap().as_path()))
+fn handle_last(config: DropConfig) -> Result<()> {
+  let entry = history::last(&config).context("Failed to read drop history")?
+    .ok_or(anyhow!("No drops found in history"))?;
+  let text = format::format_url(&config, &entry.url, &entry.key, &entry.mime_type);
+  clip::copy_to_clipboard(text.clone());
+  println!("{}", text);
+  Ok(())
+}
+
+fn handle_delete(config: DropConfig, matches: &ArgMatches) -> Result<()> {
+  let entry =
+    if matches.is_present("last") {
+      history::load(&config).map(|entries| entries.into_iter().rev().find(|e| !e.deleted))
+    } else {
+      history::resolve(&config, matches.value_of("target").unwrap())
+    }.context("Failed to read drop history")?;
+
+  let key =
+    match entry {
+      Some(ref entry) if entry.deleted => return Err(anyhow!("Drop {} has already been deleted", entry.id)),
+      Some(ref entry) => entry.key.clone(),
+      None if matches.is_present("last") => return Err(anyhow!("No drops found in history")),
+      None => {
+        let target = matches.value_of("target").unwrap();
+        if target.contains("://") {
+          return Err(anyhow!("No drop found in history with url {}", target));
+        }
+        // Local keys are file paths, deleting one that isn't in history could remove any file.
+        if config.backend == "local" {
+          return Err(anyhow!("No drop found in history for {}", target));
+        }
+        target.to_string()
+      },
+    };
+
+  let uploader = upload::create_uploader(&config).context(DropError::Config("Invalid upload backend configuration".to_string()))?;
+  if let Some(ref entry) = entry {
+    if entry.backend != uploader.name() {
+      return Err(anyhow!("Drop {} was uploaded with the {} backend but the active backend is {}", entry.id, entry.backend, uploader.name()));
+    }
+    if !entry.uploaded_with_profile(&config.profile) {
+      return Err(match entry.profile {
+        Some(ref profile) => anyhow!("Drop {} was uploaded with the {} profile, rerun with --profile {}", entry.id, profile, profile),
+        None => anyhow!("Drop {} was uploaded without a profile, rerun with --profile ''", entry.id),
+      });
+    }
+  }
+  let shared = match entry {
+    Some(ref entry) => history::sharing_entries(&history::load(&config).context("Failed to read drop history")?, entry),
+    None => Vec::new(),
+  };
+  if shared.is_empty() {
+    uploader.delete(&key).context(DropError::Upload("Failed to delete drop".to_string()))?;
+  }
+
+  if let Some(entry) = entry {
+    if let Err(err) = history::mark_deleted(&config, entry.id) {
+      eprintln!("WARNING: Failed to mark drop as deleted in history: {}", err);
+    }
+  }
+  if shared.is_empty() {
+    println!("Deleted {}", key);
   } else {
-    aws::upload_file_to_s3(&config, &file, &filename);
-    util::create_drop_url(&config, filename.unwrap_or(util::from_os_str(file.file_name().unwrap())))
+    println!("Removed drop from history, {} is still used by {}", key, format_ids(&shared));
+  }
+  Ok(())
+}
+
+fn handle_gc(config: DropConfig, matches: &ArgMatches) -> Result<()> {
+  if matches.is_present("install-lifecycle") {
+    install_lifecycle_rules(&config)?;
+  }
+
+  let mut entries = history::load(&config).context("Failed to read drop history")?;
+
+  let now = chrono::Utc::now();
+  let expired: Vec<history::HistoryEntry> = entries.iter().filter(|e| !e.deleted && e.is_expired(&now)).cloned().collect();
+  if expired.is_empty() {
+    println!("No expired drops to delete");
+    return Ok(());
+  }
+
+  let uploader = upload::create_uploader(&config).context(DropError::Config("Invalid upload backend configuration".to_string()))?;
+
+  let mut failed = 0;
+  for entry in expired {
+    if entry.backend != uploader.name() {
+      eprintln!("Skipping drop {}, it was uploaded with the {} backend", entry.id, entry.backend);
+    } else if !entry.uploaded_with_profile(&config.profile) {
+      match entry.profile {
+        Some(ref profile) => eprintln!("Skipping drop {}, it was uploaded with the {} profile", entry.id, profile),
+        None => eprintln!("Skipping drop {}, it was uploaded without a profile", entry.id),
+      }
+    } else if entry.backend == "local" && !upload::in_drop_dir(&config, &entry.key) {
+      eprintln!("Skipping drop {}, {} is outside the drop directory", entry.id, entry.key);
+    } else {
+      // Drops sharing an object with a live one are only removed from history, the last one to go
+      // deletes the object.
+      let shared = history::sharing_entries(&entries, &entry);
+      let dry_run = matches.is_present("dry-run");
+      let result =
+        if dry_run {
+          Ok(())
+        } else if shared.is_empty() {
+          uploader.delete(&entry.key).and_then(|_| history::mark_deleted(&config, entry.id))
+        } else {
+          history::mark_deleted(&config, entry.id)
+        };
+      if result.is_ok() {
+        for e in entries.iter_mut().filter(|e| e.id == entry.id) {
+          e.deleted = true;
+        }
+      }
+      match result {
+        Ok(_) if shared.is_empty() => println!("{} {}", if dry_run { "Would delete" } else { "Deleted" }, entry.summary()),
+        Ok(_) => println!("{} {}, its object is still used by {}", if dry_run { "Would remove from history" } else { "Removed from history" },
+                          entry.summary(), format_ids(&shared)),
+        Err(err) => {
+          eprintln!("ERROR: Failed to delete drop {}: {}", entry.id, err);
+          failed += 1;
+        },
+      }
+    }
+  }
+
+  if failed > 0 {
+    return Err(DropError::Upload(format!("Failed to delete {} expired drops", failed)).into());
+  }
+  Ok(())
+}
+
+fn format_ids(ids: &[u64]) -> String {
+  let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+  format!("{} {}", if ids.len() == 1 { "drop" } else { "drops" }, ids.join(", "))
+}
+
+// Rules are installed for the default ttl and every ttl still pending in history, since a rule
+// replaced by a later install would otherwise leave earlier drops without one.
+fn install_lifecycle_rules(config: &DropConfig) -> Result<()> {
+  let mut ttls: Vec<u64> = history::load(config).unwrap_or_default().iter()
+    .filter(|e| !e.deleted && e.backend == "s3")
+    .filter_map(|e| e.expires_at.map(|expires_at| (expires_at - e.timestamp).num_seconds().max(0) as u64))
+    .collect();
+  ttls.extend(config.ttl);
+
+  if ttls.is_empty() {
+    return Err(DropError::Config("No ttl configured, set one with --ttl or drop.ttl to install a lifecycle rule".to_string()).into());
+  }
+
+  let days = aws::install_lifecycle_rules(config, &ttls).context(DropError::Upload("Failed to install lifecycle rules".to_string()))?;
+  println!("Installed lifecycle rules expiring tagged drops after {} days",
+           days.iter().map(|d| d.to_string()).collect::<Vec<String>>().join(", "));
+  Ok(())
+}
+
+fn handle_clean(config: DropConfig, matches: &ArgMatches) -> Result<()> {
+  if !retention::has_policy(&config) {
+    return Err(DropError::Config("No retention policy configured, set retention_max_age, retention_max_size or retention_keep_last in the [drop] config".to_string()).into());
+  }
+
+  let dry_run = matches.is_present("dry-run");
+  let results = retention::clean_expired_drops(&config, dry_run).context("Failed to read drop directory")?;
+
+  let mut removed = 0;
+  let mut removed_size = 0;
+  for (drop, result) in results {
+    match result {
+      Ok(_) => {
+        println!("{} {} ({} bytes)", if dry_run { "Would remove" } else { "Removed" }, util::path_to_str(&drop.path), drop.size);
+        removed += 1;
+        removed_size += drop.size;
+      },
+      Err(err) => eprintln!("WARNING: Failed to remove {}: {}", util::path_to_str(&drop.path), err),
+    }
   }
+  println!("{} {} files, {} bytes", if dry_run { "Would remove" } else { "Removed" }, removed, removed_size);
+  Ok(())
 }
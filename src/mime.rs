@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str;
+
+const OCTET_STREAM: &'static str = "application/octet-stream";
+const TEXT: &'static str = "text/plain; charset=utf-8";
+
+const EXTENSIONS: &'static [(&'static str, &'static str)] = &[
+  ("png", "image/png"),
+  ("jpg", "image/jpeg"),
+  ("jpeg", "image/jpeg"),
+  ("gif", "image/gif"),
+  ("webp", "image/webp"),
+  ("bmp", "image/bmp"),
+  ("svg", "image/svg+xml"),
+  ("ico", "image/x-icon"),
+  ("mp4", "video/mp4"),
+  ("m4v", "video/mp4"),
+  ("mov", "video/quicktime"),
+  ("3gp", "video/3gpp"),
+  ("webm", "video/webm"),
+  ("mkv", "video/x-matroska"),
+  ("mp3", "audio/mpeg"),
+  ("m4a", "audio/mp4"),
+  ("ogg", "audio/ogg"),
+  ("wav", "audio/wav"),
+  ("pdf", "application/pdf"),
+  ("zip", "application/zip"),
+  ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+  ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+  ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+  ("odt", "application/vnd.oasis.opendocument.text"),
+  ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+  ("odp", "application/vnd.oasis.opendocument.presentation"),
+  ("epub", "application/epub+zip"),
+  ("jar", "application/java-archive"),
+  ("apk", "application/vnd.android.package-archive"),
+  ("gz", "application/gzip"),
+  ("tgz", "application/gzip"),
+  ("tar", "application/x-tar"),
+  ("json", "application/json"),
+  ("xml", "application/xml"),
+  ("html", "text/html; charset=utf-8"),
+  ("htm", "text/html; charset=utf-8"),
+  ("css", "text/css; charset=utf-8"),
+  ("js", "text/javascript; charset=utf-8"),
+  ("csv", "text/csv; charset=utf-8"),
+  ("md", "text/markdown; charset=utf-8"),
+  ("txt", TEXT),
+  ("log", TEXT),
+];
+
+// Formats built on a generic container share its magic bytes, so for these the extension can name
+// the more specific type. Only the listed extensions can refine a container, a zip renamed to .html
+// is still served as a zip.
+const CONTAINERS: &'static [(&'static str, &'static [&'static str])] = &[
+  ("application/zip", &["docx", "xlsx", "pptx", "odt", "ods", "odp", "epub", "jar", "apk"]),
+  ("video/webm", &["mkv"]),
+  ("video/mp4", &["m4a", "m4v", "mov", "3gp"]),
+];
+
+const SIGNATURES: &'static [(usize, &'static [u8], &'static str)] = &[
+  (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+  (0, b"\xff\xd8\xff", "image/jpeg"),
+  (0, b"GIF87a", "image/gif"),
+  (0, b"GIF89a", "image/gif"),
+  (8, b"WEBP", "image/webp"),
+  (4, b"ftypqt", "video/quicktime"),
+  (4, b"ftyp", "video/mp4"),
+  (0, b"\x1a\x45\xdf\xa3", "video/webm"),
+  (0, b"%PDF-", "application/pdf"),
+  (0, b"PK\x03\x04", "application/zip"),
+  (0, b"\x1f\x8b", "application/gzip"),
+  (257, b"ustar", "application/x-tar"),
+];
+
+// Binary formats are recognized by their magic bytes, which also covers files without an extension
+// such as stdin drops. Text formats can't be told apart by content so they rely on the extension.
+pub fn detect_mime_type(path: &Path) -> String {
+  let mut header = Vec::new();
+  if let Ok(file) = File::open(path) {
+    let _ = file.take(512).read_to_end(&mut header);
+  }
+
+  let extension = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+  match mime_type_from_magic_bytes(&header) {
+    Some(mime_type) => refine_container(mime_type, extension.as_ref().map(|ext| ext.as_str())).unwrap_or(mime_type),
+    None => extension.and_then(|ext| mime_type_from_extension(&ext))
+      .unwrap_or(if looks_like_text(&header) { TEXT } else { OCTET_STREAM }),
+  }.to_string()
+}
+
+fn refine_container(mime_type: &str, extension: Option<&str>) -> Option<&'static str> {
+  let extension = extension?;
+  CONTAINERS.iter()
+    .find(|&&(container, extensions)| container == mime_type && extensions.contains(&extension))
+    .and_then(|_| mime_type_from_extension(extension))
+}
+
+fn mime_type_from_magic_bytes(header: &[u8]) -> Option<&'static str> {
+  SIGNATURES.iter()
+    .find(|&&(offset, signature, _)| header.len() >= offset + signature.len() && &header[offset..offset + signature.len()] == signature)
+    .map(|&(_, _, mime_type)| mime_type)
+}
+
+fn mime_type_from_extension(ext: &str) -> Option<&'static str> {
+  let ext = ext.to_lowercase();
+  EXTENSIONS.iter().find(|&&(known, _)| known == ext).map(|&(_, mime_type)| mime_type)
+}
+
+fn looks_like_text(header: &[u8]) -> bool {
+  if header.contains(&0) {
+    return false;
+  }
+  match str::from_utf8(header) {
+    Ok(_) => true,
+    // The sample may have cut a multi-byte character in half
+    Err(err) => err.error_len().is_none(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::fs;
+
+  fn detect(name: &str, contents: &[u8]) -> String {
+    let dir = env::temp_dir().join(format!("drop-mime-test-{}", ::util::rand_string(8)));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    let mime_type = detect_mime_type(&path);
+    fs::remove_dir_all(dir).unwrap();
+    mime_type
+  }
+
+  #[test]
+  fn detects_mime_types() {
+    let zip = b"PK\x03\x04rest of the archive";
+    let mp4 = b"\x00\x00\x00\x20ftypM4A \x00\x00\x00\x00";
+    let ebml = b"\x1a\x45\xdf\xa3\x01\x00\x00\x00";
+    let cases: &[(&str, &[u8], &str)] = &[
+      ("shot.png", b"\x89PNG\r\n\x1a\n....", "image/png"),
+      ("shot", b"\x89PNG\r\n\x1a\n....", "image/png"),
+      ("archive.zip", zip, "application/zip"),
+      ("report.docx", zip, "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+      ("Sheet.XLSX", zip, "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+      ("app.apk", zip, "application/vnd.android.package-archive"),
+      ("page.html", zip, "application/zip"),
+      ("clip.mkv", ebml, "video/x-matroska"),
+      ("clip.webm", ebml, "video/webm"),
+      ("song.m4a", mp4, "audio/mp4"),
+      ("clip.mp4", mp4, "video/mp4"),
+      ("notes.md", b"# notes", "text/markdown; charset=utf-8"),
+      ("stdin", b"plain text", TEXT),
+      ("blob", b"\x00\x01\x02", OCTET_STREAM),
+    ];
+    for &(name, contents, expected) in cases {
+      assert_eq!(detect(name, contents), expected, "detecting {}", name);
+    }
+  }
+}
@@ -0,0 +1,304 @@
+use aws;
+use aws::{S3Client, S3Error};
+use conf::DropConfig;
+use sigv4;
+use util;
+
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::UNIX_EPOCH;
+use serde_json;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MultipartState {
+  upload_id: String,
+  key: String,
+  part_size: u64,
+  parts: Vec<(u32, String)>,
+}
+
+// Uploads a file in parts, resuming from a previous interrupted attempt if a state file exists for it.
+// Returns the key the object was stored under, which is the key of the resumed upload if there was one.
+pub fn upload(client: &S3Client, config: &DropConfig, key: &str, file_path: &Path, headers: &[(String, String)]) -> Result<String, S3Error> {
+  let state_path = state_file_path(config, file_path)?;
+  let length = file_path.metadata()?.len();
+
+  let state =
+    match load_state(&state_path).or_else(|| take_legacy_state(config, &state_path)) {
+      Some(state) if state.part_size == config.aws_part_size => {
+        eprintln!("Resuming upload of {:?} ({} of {} parts complete)", file_path, state.parts.len(), part_count(length, state.part_size));
+        state
+      },
+      _ => start_upload(client, config, key, &state_path, headers)?,
+    };
+
+  match upload_with_state(client, config, file_path, &state_path, state) {
+    Err(S3Error::Response(404, _)) => {
+      // The previous upload was aborted or expired on the remote side, start over
+      let state = start_upload(client, config, key, &state_path, headers)?;
+      upload_with_state(client, config, file_path, &state_path, state)
+    },
+    result => result,
+  }
+}
+
+fn start_upload(client: &S3Client, config: &DropConfig, key: &str, state_path: &Path, headers: &[(String, String)]) -> Result<MultipartState, S3Error> {
+  let state = MultipartState {
+    upload_id: client.create_multipart_upload(key, headers)?,
+    key: key.to_string(),
+    part_size: config.aws_part_size,
+    parts: Vec::new(),
+  };
+  save_state(state_path, &state)?;
+  Ok(state)
+}
+
+fn upload_with_state(client: &S3Client, config: &DropConfig, file_path: &Path, state_path: &Path, state: MultipartState) -> Result<String, S3Error> {
+  let length = file_path.metadata()?.len();
+  let remaining: Vec<u32> = (1..part_count(length, state.part_size) + 1).rev()
+    .filter(|number| !state.parts.iter().any(|&(done, _)| done == *number))
+    .collect();
+
+  let key = state.key.clone();
+  let upload_id = state.upload_id.clone();
+  let part_size = state.part_size;
+  let queue = Arc::new(Mutex::new(remaining));
+  let state = Arc::new(Mutex::new(state));
+  let failure: Arc<Mutex<Option<S3Error>>> = Arc::new(Mutex::new(None));
+
+  let workers: Vec<thread::JoinHandle<()>> = (0..config.aws_parallelism.max(1)).map(|_| {
+    let client = client.clone();
+    let file_path = file_path.to_path_buf();
+    let state_path = state_path.to_path_buf();
+    let key = key.clone();
+    let upload_id = upload_id.clone();
+    let queue = queue.clone();
+    let state = state.clone();
+    let failure = failure.clone();
+
+    thread::spawn(move || {
+      loop {
+        if failure.lock().unwrap().is_some() {
+          return;
+        }
+        let next = queue.lock().unwrap().pop();
+        let part_number = match next {
+          Some(part_number) => part_number,
+          None => return,
+        };
+
+        let result = read_part(&file_path, part_number, part_size)
+          .and_then(|data| client.upload_part(&key, &upload_id, part_number, &data));
+
+        match result {
+          Ok(etag) => {
+            let mut state = state.lock().unwrap();
+            state.parts.push((part_number, etag));
+            if let Err(err) = save_state(&state_path, &state) {
+              *failure.lock().unwrap() = Some(err);
+            }
+          },
+          Err(err) => {
+            *failure.lock().unwrap() = Some(err);
+          },
+        }
+      }
+    })
+  }).collect();
+
+  for worker in workers {
+    let _ = worker.join();
+  }
+
+  if let Some(err) = failure.lock().unwrap().take() {
+    eprintln!("WARNING: Multipart upload interrupted, rerun drop on {:?} to resume", file_path);
+    return Err(err);
+  }
+
+  let mut parts = state.lock().unwrap().parts.clone();
+  parts.sort();
+  client.complete_multipart_upload(&key, &upload_id, &parts)?;
+  let _ = fs::remove_file(state_path);
+  Ok(key)
+}
+
+fn read_part(file_path: &Path, part_number: u32, part_size: u64) -> Result<Vec<u8>, S3Error> {
+  let mut file = File::open(file_path)?;
+  file.seek(SeekFrom::Start((part_number as u64 - 1) * part_size))?;
+  let mut data = Vec::with_capacity(part_size as usize);
+  file.take(part_size).read_to_end(&mut data)?;
+  Ok(data)
+}
+
+fn part_count(length: u64, part_size: u64) -> u32 {
+  ((length + part_size - 1) / part_size).max(1) as u32
+}
+
+// State files are keyed by the local file's identity so that rerunning drop on the same file resumes
+// the upload, even though a fresh object name would be generated for it. The destination is part of
+// the key, an upload id is only valid on the endpoint and bucket it was created for.
+fn state_file_path(config: &DropConfig, file_path: &Path) -> Result<PathBuf, S3Error> {
+  let metadata = file_path.metadata()?;
+  let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+  let identity = format!("{}:{}:{}:{}:{}", config.profile.clone().unwrap_or_default(), aws::object_url(config, ""),
+                         util::path_to_str(&file_path.canonicalize()?), metadata.len(), modified);
+
+  let state_dir = Path::new(&config.cache_dir).join("multipart");
+  fs::create_dir_all(&state_dir)?;
+  Ok(state_dir.join(format!("{}.json", &sigv4::sha256_hex(identity.as_bytes())[..16])))
+}
+
+// State used to be kept in {dir}/.multipart, uploads interrupted before the move are moved over to
+// the cache directory when they're resumed.
+fn take_legacy_state(config: &DropConfig, state_path: &Path) -> Option<MultipartState> {
+  let legacy_path = Path::new(&config.dir).join(".multipart").join(state_path.file_name()?);
+  let state = load_state(&legacy_path)?;
+  if save_state(state_path, &state).is_ok() {
+    let _ = fs::remove_file(legacy_path);
+  }
+  Some(state)
+}
+
+fn load_state(state_path: &Path) -> Option<MultipartState> {
+  File::open(state_path).ok().and_then(|file| serde_json::from_reader(file).ok())
+}
+
+fn save_state(state_path: &Path, state: &MultipartState) -> Result<(), S3Error> {
+  let file = File::create(state_path)?;
+  serde_json::to_writer(file, state).map_err(|err| S3Error::Io(err.into()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use sigv4::Credentials;
+  use testutil::{self, Request, Response};
+  use std::env;
+
+  fn test_config(root: &Path) -> DropConfig {
+    let mut config = DropConfig::builder().dir(util::path_to_str(&root.join("drops"))).cache_dir(util::path_to_str(&root.join("cache")))
+      .aws_bucket("drops").build().unwrap();
+    config.aws_part_size = 4;
+    config.aws_parallelism = 1;
+    config
+  }
+
+  fn test_client(url: &str) -> S3Client {
+    S3Client::new(url, "us-east-1", "drops", true,
+                  Credentials { access_key: "AKIDEXAMPLE".to_string(), secret_key: "secret".to_string(), session_token: None })
+  }
+
+  fn part_response(etag: &str) -> Response {
+    Response::new(200, "").with_header("ETag", etag)
+  }
+
+  fn summary(request: &Request) -> (String, String, String) {
+    (request.method.clone(), request.path.clone(), String::from_utf8_lossy(&request.body).to_string())
+  }
+
+  fn owned(method: &str, path: &str, body: &str) -> (String, String, String) {
+    (method.to_string(), path.to_string(), body.to_string())
+  }
+
+  #[test]
+  fn uploads_files_in_parts() {
+    let root = env::temp_dir().join(format!("drop-multipart-test-{}", util::rand_string(8)));
+    fs::create_dir_all(&root).unwrap();
+    let file = root.join("big.bin");
+    fs::write(&file, "abcdefghij").unwrap();
+    let config = test_config(&root);
+    let (url, server) = testutil::serve(vec![
+      Response::new(200, "<InitiateMultipartUploadResult><UploadId>up-1</UploadId></InitiateMultipartUploadResult>"),
+      part_response("\"e1\""), part_response("\"e2\""), part_response("\"e3\""),
+      Response::new(200, "<CompleteMultipartUploadResult><Key>big.bin</Key></CompleteMultipartUploadResult>"),
+    ]);
+
+    let key = upload(&test_client(&url), &config, "big.bin", &file, &[("Content-Type".to_string(), "application/octet-stream".to_string())]).unwrap();
+
+    assert_eq!(key, "big.bin");
+    let requests = server.join().unwrap();
+    assert_eq!(requests.iter().map(summary).collect::<Vec<_>>(), vec![
+      owned("POST", "/drops/big.bin?uploads=", ""),
+      owned("PUT", "/drops/big.bin?partNumber=1&uploadId=up-1", "abcd"),
+      owned("PUT", "/drops/big.bin?partNumber=2&uploadId=up-1", "efgh"),
+      owned("PUT", "/drops/big.bin?partNumber=3&uploadId=up-1", "ij"),
+      owned("POST", "/drops/big.bin?uploadId=up-1",
+            "<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>\"e1\"</ETag></Part>\
+             <Part><PartNumber>2</PartNumber><ETag>\"e2\"</ETag></Part>\
+             <Part><PartNumber>3</PartNumber><ETag>\"e3\"</ETag></Part></CompleteMultipartUpload>"),
+    ]);
+    assert_eq!(requests[0].header("content-type"), Some("application/octet-stream"));
+    assert!(load_state(&state_file_path(&config, &file).unwrap()).is_none());
+    fs::remove_dir_all(root).unwrap();
+  }
+
+  #[test]
+  fn resumes_interrupted_uploads() {
+    let root = env::temp_dir().join(format!("drop-multipart-test-{}", util::rand_string(8)));
+    fs::create_dir_all(&root).unwrap();
+    let file = root.join("big.bin");
+    fs::write(&file, "abcdefghij").unwrap();
+    let config = test_config(&root);
+    let state = MultipartState { upload_id: "up-1".to_string(), key: "first-key.bin".to_string(), part_size: 4, parts: vec![(2, "\"e2\"".to_string())] };
+    save_state(&state_file_path(&config, &file).unwrap(), &state).unwrap();
+    let (url, server) = testutil::serve(vec![part_response("\"e1\""), part_response("\"e3\""), Response::new(200, "<CompleteMultipartUploadResult/>")]);
+
+    let key = upload(&test_client(&url), &config, "second-key.bin", &file, &[]).unwrap();
+
+    assert_eq!(key, "first-key.bin");
+    let requests = server.join().unwrap();
+    assert_eq!(requests.iter().map(summary).collect::<Vec<_>>(), vec![
+      owned("PUT", "/drops/first-key.bin?partNumber=1&uploadId=up-1", "abcd"),
+      owned("PUT", "/drops/first-key.bin?partNumber=3&uploadId=up-1", "ij"),
+      owned("POST", "/drops/first-key.bin?uploadId=up-1",
+            "<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>\"e1\"</ETag></Part>\
+             <Part><PartNumber>2</PartNumber><ETag>\"e2\"</ETag></Part>\
+             <Part><PartNumber>3</PartNumber><ETag>\"e3\"</ETag></Part></CompleteMultipartUpload>"),
+    ]);
+    fs::remove_dir_all(root).unwrap();
+  }
+
+  #[test]
+  fn keys_state_by_destination() {
+    let root = env::temp_dir().join(format!("drop-multipart-test-{}", util::rand_string(8)));
+    fs::create_dir_all(&root).unwrap();
+    let file = root.join("big.bin");
+    fs::write(&file, "abcdefghij").unwrap();
+    let config = test_config(&root);
+    let mut other_endpoint = test_config(&root);
+    other_endpoint.aws_endpoint = Some("http://localhost:9000".to_string());
+    let mut other_profile = test_config(&root);
+    other_profile.profile = Some("work".to_string());
+
+    let path = state_file_path(&config, &file).unwrap();
+    assert_eq!(path, state_file_path(&test_config(&root), &file).unwrap());
+    assert!(path != state_file_path(&other_endpoint, &file).unwrap());
+    assert!(path != state_file_path(&other_profile, &file).unwrap());
+    fs::remove_dir_all(root).unwrap();
+  }
+
+  #[test]
+  fn moves_state_from_the_legacy_directory() {
+    let root = env::temp_dir().join(format!("drop-multipart-test-{}", util::rand_string(8)));
+    let legacy_dir = root.join("drops").join(".multipart");
+    let cache_dir = root.join("cache").join("multipart");
+    fs::create_dir_all(&legacy_dir).unwrap();
+    fs::create_dir_all(&cache_dir).unwrap();
+    let state = MultipartState { upload_id: "upload".to_string(), key: "big.tar.gz".to_string(), part_size: 5, parts: vec![(1, "\"etag\"".to_string())] };
+    save_state(&legacy_dir.join("0123456789abcdef.json"), &state).unwrap();
+
+    let config = DropConfig::builder().dir(util::path_to_str(&root.join("drops"))).cache_dir(util::path_to_str(&root.join("cache"))).build().unwrap();
+    let state_path = cache_dir.join("0123456789abcdef.json");
+    let resumed = load_state(&state_path).or_else(|| take_legacy_state(&config, &state_path)).unwrap();
+    assert_eq!((resumed.upload_id.as_str(), resumed.key.as_str(), resumed.parts.len()), ("upload", "big.tar.gz", 1));
+    assert!(load_state(&state_path).is_some());
+    assert!(!legacy_dir.join("0123456789abcdef.json").exists());
+    assert!(take_legacy_state(&config, &cache_dir.join("fedcba9876543210.json")).is_none());
+
+    fs::remove_dir_all(root).unwrap();
+  }
+}
@@ -0,0 +1,337 @@
+use conf::DropConfig;
+use util;
+
+use std::env;
+use std::path::Path;
+use anyhow::Result;
+use chrono::Local;
+use chrono::format::{Item, StrftimeItems};
+use sys_info;
+
+const TOKENS: &'static [&'static str] = &["date", "time", "host", "user", "orig", "ext", "rand", "sha256", "kind"];
+
+// Extensions made of more than one part, which would otherwise be split at their last dot.
+const COMPOUND_EXTENSIONS: &'static [&'static str] = &["tar.gz", "tar.bz2", "tar.xz", "tar.zst", "tar.lz", "tar.lz4", "tar.lzma",
+                                                       "tar.br", "d.ts", "d.mts", "d.cts", "min.js", "min.css", "js.map"];
+
+#[derive(Debug)]
+enum Part {
+  Literal(String),
+  Token(String, Option<String>),
+}
+
+// Picks the remote key for a drop. The filename template wins unless a filename was given
+// explicitly, then the hash strategy, and otherwise the already generated filename is used.
+pub fn generate_key(config: &DropConfig, file: &Path, filename: Option<String>, original: Option<String>, kind: &str) -> Result<String> {
+  match config.filename_template {
+    Some(ref template) if config.filename.is_none() => expand_filename_template(config, template, file, original, kind),
+    _ if config.filename_strategy == "hash" => hash_filename(config, file, config.filename.clone().or(original)),
+    _ => Ok(filename.unwrap_or(file.file_name().map(|s| util::from_os_str(s)).unwrap_or_default())),
+  }
+}
+
+// Whether keys are derived from the file contents, in which case an existing upload with the same
+// key can be reused.
+pub fn is_content_addressed(config: &DropConfig) -> bool {
+  config.filename_strategy == "hash" && (config.filename_template.is_none() || config.filename.is_some())
+}
+
+fn hash_filename(config: &DropConfig, file: &Path, original: Option<String>) -> Result<String> {
+  let name = original.or(file.file_name().map(|s| util::from_os_str(s))).unwrap_or_default();
+  let ext = sanitize(&config.extension.clone().or(split_filename(&name).1).unwrap_or_default());
+  let hash = util::sha256_file(file)?;
+  Ok(if ext.is_empty() { hash } else { format!("{}.{}", hash, ext) })
+}
+
+// Splits a filename into its base and extension. Known compound extensions are kept whole, any
+// other extension is the part after the last dot as long as it looks like one, and leading dots
+// belong to the base so dotfiles keep their name.
+pub fn split_filename(filename: &str) -> (String, Option<String>) {
+  let stem_start = filename.len() - filename.trim_start_matches('.').len();
+  for ext in COMPOUND_EXTENSIONS {
+    let suffix_start = filename.len().saturating_sub(ext.len() + 1);
+    if suffix_start > stem_start && filename.is_char_boundary(suffix_start)
+        && filename[suffix_start..].eq_ignore_ascii_case(&format!(".{}", ext)) {
+      return (filename[..suffix_start].to_string(), Some(filename[suffix_start + 1..].to_string()));
+    }
+  }
+
+  match filename.rfind('.') {
+    Some(idx) if idx > stem_start && is_extension(&filename[idx + 1..]) => {
+      (filename[..idx].to_string(), Some(filename[idx + 1..].to_string()))
+    },
+    _ => (filename.to_string(), None),
+  }
+}
+
+fn is_extension(value: &str) -> bool {
+  !value.is_empty() && value.len() <= 12 && value.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+// Replaces anything that isn't safe to use unescaped in urls and object keys, such as whitespace,
+// quotes, '#', '?' and '%', with a dash. Letters from other alphabets are kept.
+pub fn sanitize(value: &str) -> String {
+  let mut sanitized = String::new();
+  for c in value.chars() {
+    let safe = c.is_alphanumeric() || c == '.' || c == '_' || c == '-' || c == '~';
+    if safe {
+      sanitized.push(c);
+    } else if !sanitized.ends_with('-') {
+      sanitized.push('-');
+    }
+  }
+  sanitized
+}
+
+// Names a drop from a filename template such as "{date:%Y/%m}/{orig}-{rand:6}.{ext}". Templates
+// may contain '/' to organize remote keys into folders.
+pub fn expand_filename_template(config: &DropConfig, template: &str, file: &Path, original: Option<String>, kind: &str) -> Result<String> {
+  let (orig, orig_ext) = match original {
+    Some(name) => split_filename(&name),
+    None => (String::new(), split_filename(&file.file_name().map(|s| util::from_os_str(s)).unwrap_or_default()).1),
+  };
+  let ext = config.extension.clone().or(orig_ext).unwrap_or_default();
+
+  // Avoid a dangling dot when there's no extension to put after it
+  let template = if ext.is_empty() { template.replace(".{ext}", "") } else { template.to_string() };
+
+  let now = Local::now();
+  let mut key = String::new();
+  for part in parse_template(&template)? {
+    match part {
+      Part::Literal(literal) => key.push_str(&literal),
+      Part::Token(name, arg) => {
+        let value = match name.as_ref() {
+          "date" => now.format(arg.as_ref().map(|a| a.as_str()).unwrap_or("%Y-%m-%d")).to_string(),
+          "time" => now.format(arg.as_ref().map(|a| a.as_str()).unwrap_or("%H-%M-%S")).to_string(),
+          "host" => sys_info::hostname().unwrap_or("localhost".to_string()),
+          "user" => env::var("USER").unwrap_or("user".to_string()),
+          "orig" => orig.clone(),
+          "ext" => ext.clone(),
+          "rand" => util::unique_string(config, parse_length(&arg)?),
+          "sha256" => {
+            let hash = util::sha256_file(file)?;
+            hash[..parse_length(&arg)?.unwrap_or(hash.len()).min(hash.len())].to_string()
+          },
+          _ => kind.to_string(),
+        };
+        key.push_str(&value);
+      },
+    }
+  }
+  Ok(clean_key(&key))
+}
+
+// Checks a template up front so that mistakes surface as config errors rather than after a capture.
+pub fn validate_filename_template(template: &str) -> Result<()> {
+  for part in parse_template(template)? {
+    if let Part::Token(name, arg) = part {
+      if name == "rand" || name == "sha256" {
+        parse_length(&arg)?;
+      } else if let Some(format) = arg.filter(|_| name == "date" || name == "time") {
+        if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+          return Err(anyhow!("Invalid date format in filename template: {}", format));
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+fn parse_template(template: &str) -> Result<Vec<Part>> {
+  let mut parts = Vec::new();
+  let mut rest = template;
+  while let Some(start) = rest.find('{') {
+    if start > 0 {
+      parts.push(Part::Literal(rest[..start].to_string()));
+    }
+    let end = rest[start..].find('}').map(|end| start + end)
+      .ok_or(anyhow!("Unclosed token in filename template: {}", template))?;
+    let token = &rest[start + 1..end];
+    let mut split = token.splitn(2, ':');
+    let name = split.next().unwrap_or("").to_string();
+    if !TOKENS.contains(&name.as_str()) {
+      return Err(anyhow!("Unrecognized token {{{}}} in filename template", token));
+    }
+    parts.push(Part::Token(name, split.next().map(|arg| arg.to_string())));
+    rest = &rest[end + 1..];
+  }
+  if !rest.is_empty() {
+    parts.push(Part::Literal(rest.to_string()));
+  }
+  Ok(parts)
+}
+
+fn parse_length(arg: &Option<String>) -> Result<Option<usize>> {
+  match *arg {
+    Some(ref arg) => arg.parse::<usize>().map(Some).map_err(|_| anyhow!("Invalid length in filename template: {}", arg)),
+    None => Ok(None),
+  }
+}
+
+// Keys are always relative, so leading slashes and empty, '.' or '..' segments are dropped.
+fn clean_key(key: &str) -> String {
+  key.split('/')
+    .map(|segment| sanitize(segment))
+    .filter(|segment| !segment.is_empty() && segment != "." && segment != "..")
+    .collect::<Vec<String>>()
+    .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use conf::DropConfigBuilder;
+  use util;
+
+  use std::env;
+
+  #[test]
+  fn splits_filenames() {
+    let cases = vec![
+      ("notes.txt", "notes", Some("txt")),
+      ("v1.2.3-notes.txt", "v1.2.3-notes", Some("txt")),
+      ("v1.2.3-notes", "v1.2.3-notes", None),
+      ("backup.tar.gz", "backup", Some("tar.gz")),
+      ("backup.2024.TAR.ZST", "backup.2024", Some("TAR.ZST")),
+      ("index.d.ts", "index", Some("d.ts")),
+      ("jquery.min.js", "jquery", Some("min.js")),
+      (".bashrc", ".bashrc", None),
+      (".bashrc.bak", ".bashrc", Some("bak")),
+      ("..hidden", "..hidden", None),
+      (".tar.gz", ".tar", Some("gz")),
+      ("archive.", "archive.", None),
+      ("README", "README", None),
+      ("photo 2024.jpeg", "photo 2024", Some("jpeg")),
+      ("résumé.pdf", "résumé", Some("pdf")),
+    ];
+    for (filename, base, ext) in cases {
+      assert_eq!(split_filename(filename), (base.to_string(), ext.map(|e| e.to_string())), "splitting {}", filename);
+    }
+  }
+
+  #[test]
+  fn sanitizes_unsafe_characters() {
+    let cases = vec![
+      ("notes", "notes"),
+      ("my notes", "my-notes"),
+      ("a  #b?c%d", "a-b-c-d"),
+      ("\"quoted\"", "-quoted-"),
+      ("résumé", "résumé"),
+      ("v1.2_final~", "v1.2_final~"),
+      ("a/b", "a-b"),
+    ];
+    for (value, expected) in cases {
+      assert_eq!(sanitize(value), expected, "sanitizing {}", value);
+    }
+  }
+
+  // Expected names use {u} for the generated unique string, which is checked by length.
+  #[test]
+  fn generates_filenames_for_strategies() {
+    let cases = vec![
+      ("exact", "notes.txt", "notes.txt"),
+      ("prepend", "notes.txt", "{u}--notes.txt"),
+      ("append", "notes.txt", "notes--{u}.txt"),
+      ("replace", "notes.txt", "{u}.txt"),
+      ("hash", "notes.txt", "{u}.txt"),
+      ("exact", "v1.2.3-notes.txt", "v1.2.3-notes.txt"),
+      ("prepend", "v1.2.3-notes.txt", "{u}--v1.2.3-notes.txt"),
+      ("append", "logs.tar.gz", "logs--{u}.tar.gz"),
+      ("exact", "index.d.ts", "index.d.ts"),
+      ("exact", ".bashrc", ".bashrc"),
+      ("prepend", ".bashrc", "{u}--.bashrc"),
+      ("exact", "my report (final).pdf", "my-report-final-.pdf"),
+      ("exact", "README", "README"),
+    ];
+    for (strategy, filename, expected) in cases {
+      let config = test_config().filename_strategy(strategy).build().unwrap();
+      let generated = util::generate_filename(&config, Some(filename.to_string()), None);
+      assert_unique_pattern(&generated, expected, config.unique_length);
+    }
+  }
+
+  #[test]
+  fn generates_filenames_without_an_original() {
+    let config = test_config().build().unwrap();
+    assert_unique_pattern(&util::generate_filename(&config, None, Some("png".to_string())), "{u}.png", 10);
+    assert_unique_pattern(&util::generate_filename(&config, None, None), "{u}", 10);
+
+    let config = test_config().extension("tar.gz").build().unwrap();
+    assert_unique_pattern(&util::generate_filename(&config, Some("logs".to_string()), None), "{u}--logs.tar.gz", 10);
+  }
+
+  #[test]
+  fn expands_filename_templates() {
+    let file = env::temp_dir().join("drop-naming-test.txt");
+    ::std::fs::write(&file, b"hello").unwrap();
+    let config = test_config().build().unwrap();
+    let cases = vec![
+      ("{orig}.{ext}", Some("backup.tar.gz"), "backup.tar.gz"),
+      ("{kind}/{orig}.{ext}", Some("README"), "file/README"),
+      ("/../{orig}-{sha256:8}.{ext}", Some("v1.2.3-notes.txt"), "v1.2.3-notes-2cf24dba.txt"),
+      ("{orig} copy.{ext}", Some("my notes.md"), "my-notes-copy.md"),
+    ];
+    for (template, original, expected) in cases {
+      let key = expand_filename_template(&config, template, &file, original.map(|o| o.to_string()), "file").unwrap();
+      assert_eq!(key, expected, "expanding {}", template);
+    }
+  }
+
+  #[test]
+  fn expands_generated_tokens() {
+    let file = env::temp_dir().join(format!("drop-naming-test-{}.png", util::rand_string(8)));
+    ::std::fs::write(&file, b"hello").unwrap();
+    let config = test_config().build().unwrap();
+
+    let key = expand_filename_template(&config, "{date:%Y}/{kind}-{rand:6}.{ext}", &file, None, "screenshot").unwrap();
+    assert_unique_pattern(&key, &format!("{}/screenshot-{{u}}.png", Local::now().format("%Y")), 6);
+
+    let key = expand_filename_template(&config, "{sha256}", &file, None, "file").unwrap();
+    assert_eq!(key, "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+
+    let key = expand_filename_template(&config, "{orig}/{host}.{ext}", &file, None, "file").unwrap();
+    assert!(!key.contains('/') && key.ends_with(".png"), "expected an empty {{orig}} segment to be dropped from {}", key);
+
+    let config = test_config().extension("jpg").build().unwrap();
+    let key = expand_filename_template(&config, "{orig}.{ext}", &file, Some("shot.png".to_string()), "file").unwrap();
+    assert_eq!(key, "shot.jpg");
+    ::std::fs::remove_file(file).unwrap();
+  }
+
+  #[test]
+  fn validates_filename_templates() {
+    assert!(validate_filename_template("{date:%Y/%m}/{orig}-{rand:6}.{ext}").is_ok());
+    assert!(validate_filename_template("{time}-{user}@{host}-{sha256:12}").is_ok());
+    for template in &["{orig", "{name}.{ext}", "{rand:six}", "{sha256:-1}", "{date:%Q}"] {
+      assert!(validate_filename_template(template).is_err(), "expected {} to be rejected", template);
+    }
+  }
+
+  #[test]
+  fn prefers_explicit_filenames_over_templates() {
+    let file = env::temp_dir().join("drop-naming-test.txt");
+    let config = test_config().filename_template("{kind}/{orig}.{ext}").build().unwrap();
+    assert_eq!(generate_key(&config, &file, None, Some("notes.txt".to_string()), "file").unwrap(), "file/notes.txt");
+    assert!(!is_content_addressed(&config));
+
+    let config = test_config().filename_template("{kind}/{orig}.{ext}").filename("exact.txt").filename_strategy("exact").build().unwrap();
+    assert_eq!(generate_key(&config, &file, Some("exact.txt".to_string()), None, "file").unwrap(), "exact.txt");
+  }
+
+  fn test_config() -> DropConfigBuilder {
+    DropConfigBuilder::new().dir(util::path_to_str(&env::temp_dir())).unique_length(10)
+  }
+
+  fn assert_unique_pattern(actual: &str, expected: &str, unique_length: usize) {
+    match expected.find("{u}") {
+      Some(idx) => {
+        let suffix = &expected[idx + 3..];
+        let matches = actual.len() == expected.len() - 3 + unique_length && actual.starts_with(&expected[..idx])
+          && actual.ends_with(suffix) && actual[idx..actual.len() - suffix.len()].chars().all(|c| c.is_ascii_alphanumeric());
+        assert!(matches, "expected {} to match {}", actual, expected);
+      },
+      None => assert_eq!(actual, expected),
+    }
+  }
+}
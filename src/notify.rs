@@ -13,8 +13,8 @@ pub fn send_screenshot_notification(file_path: &Path, config: &DropConfig) {
     .arg("Url is in clipboard.");
 
   let result = util::run_command_and_wait(&mut cmd, "NOTIFY SEND", config);
-  if !result.success() {
-    println!("WARNING: Failed to create desktop notification");
+  if !result.map(|status| status.success()).unwrap_or(false) {
+    eprintln!("WARNING: Failed to create desktop notification");
   }
 }
 
@@ -25,8 +25,8 @@ pub fn send_upload_notification(file_name: String, config: &DropConfig) {
     .arg(format!("Url is in clipboard ({})", file_name));
 
   let result = util::run_command_and_wait(&mut cmd, "NOTIFY SEND", config);
-  if !result.success() {
-    println!("WARNING: Failed to create desktop notification");
+  if !result.map(|status| status.success()).unwrap_or(false) {
+    eprintln!("WARNING: Failed to create desktop notification");
   }
 }
 
@@ -37,8 +37,8 @@ pub fn send_screenshot_notification(file_path: &Path, config: &DropConfig) {
   cmd.arg("-e").arg(format!("display notification \"Drop url in clipboard.\" with title \"drop\""));
 
   let result = util::run_command_and_wait(&mut cmd, "OSA SCRIPT", config);
-  if !result.success() {
-    println!("WARNING: Failed to create desktop notification");
+  if !result.map(|status| status.success()).unwrap_or(false) {
+    eprintln!("WARNING: Failed to create desktop notification");
   }
 }
 
@@ -49,7 +49,7 @@ pub fn send_upload_notification(file_name: String, config: &DropConfig) {
   cmd.arg("-e").arg(format!("display notification \"Drop upload completed: {}\" with title \"drop\"", file_name));
 
   let result = util::run_command_and_wait(&mut cmd, "OSA SCRIPT", config);
-  if !result.success() {
-    println!("WARNING: Failed to create desktop notification");
+  if !result.map(|status| status.success()).unwrap_or(false) {
+    eprintln!("WARNING: Failed to create desktop notification");
   }
 }
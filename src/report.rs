@@ -0,0 +1,103 @@
+use mime;
+use upload::Upload;
+use util;
+
+use std::path::Path;
+use std::time::Duration;
+use anyhow::Result;
+use serde_json;
+
+// Describes a finished drop, printed as a single JSON object with --output json so that scripts and
+// editor plugins don't have to scrape the url out of human readable output.
+#[derive(Debug, Clone, Serialize)]
+pub struct DropReport {
+  pub url: String,
+  pub local_path: String,
+  pub key: String,
+  pub size: u64,
+  pub mime_type: String,
+  pub backend: String,
+  pub kind: String,
+  pub deduplicated: bool,
+  pub timing: Timing,
+}
+
+// Durations in milliseconds, capture is only present for screenshots and screencasts.
+#[derive(Debug, Clone, Serialize)]
+pub struct Timing {
+  pub capture_ms: Option<u64>,
+  pub upload_ms: u64,
+  pub total_ms: u64,
+}
+
+impl DropReport {
+  pub fn new(file: &Path, upload: &Upload, kind: &str, backend: &str, upload_time: Duration) -> DropReport {
+    DropReport {
+      url: upload.url.clone(),
+      local_path: util::path_to_str(&file.canonicalize().unwrap_or(file.to_path_buf())),
+      key: upload.key.clone(),
+      size: file.metadata().map(|m| m.len()).unwrap_or(0),
+      mime_type: mime::detect_mime_type(file),
+      backend: backend.to_string(),
+      kind: kind.to_string(),
+      deduplicated: false,
+      timing: Timing {
+        capture_ms: None,
+        upload_ms: as_millis(upload_time),
+        total_ms: as_millis(upload_time),
+      },
+    }
+  }
+
+  pub fn with_timing(mut self, capture_time: Option<Duration>, total_time: Duration) -> DropReport {
+    self.timing.capture_ms = capture_time.map(as_millis);
+    self.timing.total_ms = as_millis(total_time);
+    self
+  }
+
+  pub fn to_json(&self) -> Result<String> {
+    Ok(serde_json::to_string(self)?)
+  }
+}
+
+fn as_millis(duration: Duration) -> u64 {
+  duration.as_secs() * 1000 + duration.subsec_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::fs;
+
+  fn parse(json: &str) -> serde_json::Value {
+    serde_json::from_str(json).unwrap()
+  }
+
+  #[test]
+  fn describes_drops_as_json() {
+    let file = env::temp_dir().join(format!("drop-report-test-{}.txt", util::rand_string(8)));
+    fs::write(&file, "hello").unwrap();
+    let path = util::path_to_str(&file.canonicalize().unwrap());
+
+    let upload = Upload { key: "2024/notes.txt".to_string(), url: "https://drops.example.com/2024/notes.txt".to_string() };
+    let report = DropReport::new(&file, &upload, "screenshot", "s3", Duration::from_millis(312))
+      .with_timing(Some(Duration::from_millis(1500)), Duration::from_millis(1815));
+    assert_eq!(parse(&report.to_json().unwrap()), parse(&format!(r#"{{
+      "url": "https://drops.example.com/2024/notes.txt", "local_path": {}, "key": "2024/notes.txt", "size": 5,
+      "mime_type": "text/plain; charset=utf-8", "backend": "s3", "kind": "screenshot", "deduplicated": false,
+      "timing": {{"capture_ms": 1500, "upload_ms": 312, "total_ms": 1815}}
+    }}"#, serde_json::to_string(&path).unwrap())));
+
+    // Local drops of existing files have no capture step, and the key is the file itself
+    let upload = Upload { key: path.clone(), url: format!("file://{}", path) };
+    let report = DropReport::new(&file, &upload, "file", "local", Duration::from_millis(2));
+    assert_eq!(parse(&report.to_json().unwrap()), parse(&format!(r#"{{
+      "url": {}, "local_path": {}, "key": {}, "size": 5,
+      "mime_type": "text/plain; charset=utf-8", "backend": "local", "kind": "file", "deduplicated": false,
+      "timing": {{"capture_ms": null, "upload_ms": 2, "total_ms": 2}}
+    }}"#, serde_json::to_string(&format!("file://{}", path)).unwrap(), serde_json::to_string(&path).unwrap(),
+          serde_json::to_string(&path).unwrap())));
+    fs::remove_file(file).unwrap();
+  }
+}
@@ -0,0 +1,153 @@
+use conf::DropConfig;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub struct LocalDrop {
+  pub path: PathBuf,
+  pub size: u64,
+  pub modified: SystemTime,
+}
+
+pub fn has_policy(config: &DropConfig) -> bool {
+  config.retention_max_age.is_some() || config.retention_max_size.is_some() || config.retention_keep_last.is_some()
+}
+
+// Lists the files in the drop directory that fall outside the retention policy. Hidden files such
+// as history and upload state are never touched.
+pub fn find_expired_drops(config: &DropConfig) -> io::Result<Vec<LocalDrop>> {
+  Ok(select_expired_drops(config, list_local_drops(Path::new(&config.dir))?, SystemTime::now()))
+}
+
+// Removes the expired drops, or only lists them for a dry run, returning each with its outcome.
+pub fn clean_expired_drops(config: &DropConfig, dry_run: bool) -> io::Result<Vec<(LocalDrop, io::Result<()>)>> {
+  Ok(find_expired_drops(config)?.into_iter()
+    .map(|drop| {
+      let result = if dry_run { Ok(()) } else { fs::remove_file(&drop.path) };
+      (drop, result)
+    })
+    .collect())
+}
+
+// Files are kept newest first until one of the limits is reached. The newest keep_last files are
+// always kept, even when they're older or larger than the other limits allow.
+fn select_expired_drops(config: &DropConfig, mut drops: Vec<LocalDrop>, now: SystemTime) -> Vec<LocalDrop> {
+  drops.sort_by(|a, b| b.modified.cmp(&a.modified));
+
+  let mut total_size = 0;
+  let mut expired = Vec::new();
+  for (idx, drop) in drops.into_iter().enumerate() {
+    total_size += drop.size;
+    let age = now.duration_since(drop.modified).unwrap_or(Duration::from_secs(0));
+
+    let protected = config.retention_keep_last.map(|keep_last| idx < keep_last).unwrap_or(false);
+    let too_old = config.retention_max_age.map(|max_age| age.as_secs() > max_age).unwrap_or(false);
+    let too_large = config.retention_max_size.map(|max_size| total_size > max_size).unwrap_or(false);
+    let too_many = config.retention_keep_last.is_some() && !protected;
+    if !protected && (too_old || too_large || too_many) {
+      expired.push(drop);
+    }
+  }
+  expired
+}
+
+fn list_local_drops(dir: &Path) -> io::Result<Vec<LocalDrop>> {
+  let mut drops = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let metadata = entry.metadata()?;
+    if !metadata.is_file() || entry.file_name().to_string_lossy().starts_with('.') {
+      continue;
+    }
+    drops.push(LocalDrop { path: entry.path(), size: metadata.len(), modified: metadata.modified()? });
+  }
+  Ok(drops)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use util;
+  use std::env;
+
+  const HOUR: u64 = 3600;
+
+  // Drops named after their age in hours.
+  fn drops(sizes: &[(u64, u64)], now: SystemTime) -> Vec<LocalDrop> {
+    sizes.iter().map(|&(hours, size)| {
+      LocalDrop { path: PathBuf::from(format!("{}h", hours)), size: size, modified: now - Duration::from_secs(hours * HOUR) }
+    }).collect()
+  }
+
+  fn expired(config: DropConfig, sizes: &[(u64, u64)]) -> Vec<String> {
+    let now = SystemTime::now();
+    select_expired_drops(&config, drops(sizes, now), now).into_iter().map(|drop| util::path_to_str(&drop.path)).collect()
+  }
+
+  fn policy() -> DropConfig {
+    DropConfig::builder().dir(util::path_to_str(&env::temp_dir())).build().unwrap()
+  }
+
+  fn with_policy(max_age: Option<u64>, max_size: Option<u64>, keep_last: Option<usize>) -> DropConfig {
+    let mut config = policy();
+    config.retention_max_age = max_age;
+    config.retention_max_size = max_size;
+    config.retention_keep_last = keep_last;
+    config
+  }
+
+  #[test]
+  fn expires_drops_by_each_limit() {
+    let sizes = [(30, 10), (1, 10), (50, 10), (2, 10)];
+    assert!(expired(policy(), &sizes).is_empty());
+    assert_eq!(expired(with_policy(Some(24 * HOUR), None, None), &sizes), vec!["30h", "50h"]);
+    assert_eq!(expired(with_policy(None, Some(25), None), &sizes), vec!["30h", "50h"]);
+    assert_eq!(expired(with_policy(None, Some(20), None), &sizes), vec!["30h", "50h"]);
+    assert_eq!(expired(with_policy(None, None, Some(3)), &sizes), vec!["50h"]);
+    assert_eq!(expired(with_policy(None, None, Some(0)), &sizes), vec!["1h", "2h", "30h", "50h"]);
+  }
+
+  #[test]
+  fn combines_limits() {
+    let sizes = [(1, 100), (2, 10), (30, 10), (40, 10), (50, 10)];
+    assert_eq!(expired(with_policy(Some(24 * HOUR), Some(1000), None), &sizes), vec!["30h", "40h", "50h"]);
+    assert_eq!(expired(with_policy(Some(45 * HOUR), Some(115), None), &sizes), vec!["30h", "40h", "50h"]);
+    assert_eq!(expired(with_policy(Some(45 * HOUR), Some(1000), Some(4)), &sizes), vec!["50h"]);
+  }
+
+  #[test]
+  fn always_keeps_the_most_recent_drops() {
+    let sizes = [(1, 100), (30, 100), (50, 100)];
+    assert_eq!(expired(with_policy(Some(HOUR / 2), None, Some(2)), &sizes), vec!["50h"]);
+    assert_eq!(expired(with_policy(None, Some(50), Some(2)), &sizes), vec!["50h"]);
+    assert_eq!(expired(with_policy(Some(HOUR / 2), Some(50), Some(5)), &sizes), Vec::<String>::new());
+  }
+
+  #[test]
+  fn cleans_only_visible_files_in_the_drop_directory() {
+    let dir = env::temp_dir().join(format!("drop-retention-test-{}", util::rand_string(8)));
+    fs::create_dir_all(dir.join("folder")).unwrap();
+    for name in &["shot.png", "notes.txt", ".history", ".hidden.png", "folder/inner.txt"] {
+      fs::write(dir.join(name), "contents").unwrap();
+    }
+    let mut config = policy();
+    config.dir = util::path_to_str(&dir);
+    config.retention_keep_last = Some(0);
+
+    let mut names: Vec<String> = clean_expired_drops(&config, true).unwrap().into_iter()
+      .map(|(drop, result)| { assert!(result.is_ok()); util::from_os_str(drop.path.file_name().unwrap()) })
+      .collect();
+    names.sort();
+    assert_eq!(names, vec!["notes.txt", "shot.png"]);
+    assert!(dir.join("shot.png").exists() && dir.join("notes.txt").exists());
+
+    assert_eq!(clean_expired_drops(&config, false).unwrap().len(), 2);
+    assert!(!dir.join("shot.png").exists() && !dir.join("notes.txt").exists());
+    for name in &[".history", ".hidden.png", "folder/inner.txt"] {
+      assert!(dir.join(name).exists(), "{} was removed", name);
+    }
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
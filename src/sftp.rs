@@ -0,0 +1,181 @@
+use conf::DropConfig;
+use upload::{Upload, Uploader};
+use util;
+
+use std;
+use std::fs::File;
+use std::io;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+
+// Copies files over SFTP into a remote directory served by a web server at the configured host.
+pub struct SftpUploader<'a> {
+  config: &'a DropConfig,
+  host: String,
+}
+
+impl<'a> SftpUploader<'a> {
+  pub fn new(config: &'a DropConfig) -> Result<SftpUploader<'a>> {
+    let host = config.sftp_host.clone().ok_or(anyhow!("SFTP backend requires sftp.host to be configured"))?;
+    if config.host.is_none() && config.url_template.is_none() {
+      return Err(anyhow!("SFTP backend requires a drop host or url template to be configured for generating urls"));
+    }
+    Ok(SftpUploader { config: config, host: host })
+  }
+
+  fn connect(&self) -> Result<Sftp> {
+    let tcp = TcpStream::connect((self.host.as_str(), self.config.sftp_port))?;
+    let mut session = Session::new()?;
+    session.set_tcp_stream(tcp);
+    session.handshake()?;
+    self.verify_host_key(&session)?;
+
+    let user = self.config.sftp_user.clone().or(std::env::var("USER").ok())
+      .ok_or(anyhow!("SFTP backend requires sftp.user to be configured"))?;
+    match self.config.sftp_key_file {
+      Some(ref key_file) => session.userauth_pubkey_file(&user, None, Path::new(key_file), None)?,
+      None => session.userauth_agent(&user)?,
+    }
+
+    if !session.authenticated() {
+      return Err(anyhow!("Failed to authenticate with SFTP server {} as {}", self.host, user));
+    }
+    Ok(session.sftp()?)
+  }
+
+  fn verify_host_key(&self, session: &Session) -> Result<()> {
+    let (key, _) = session.host_key().ok_or(anyhow!("SFTP server {} did not provide a host key", self.host))?;
+    let known_hosts_file = util::home_dir().unwrap_or_default().join(".ssh/known_hosts");
+    check_known_host(session, &known_hosts_file, &self.host, self.config.sftp_port, key)
+  }
+
+  fn remote_path(&self, key: &str) -> PathBuf {
+    Path::new(&self.config.sftp_remote_dir).join(key)
+  }
+}
+
+impl<'a> Uploader for SftpUploader<'a> {
+  fn name(&self) -> &'static str {
+    "sftp"
+  }
+
+  fn upload(&self, file_path: &Path, key: &str) -> Result<Upload> {
+    let sftp = self.connect()?;
+    let remote_path = self.remote_path(key);
+    if let Some(parent) = remote_path.parent() {
+      ensure_remote_directory_exists(&sftp, parent);
+    }
+
+    let mut local = File::open(file_path)?;
+    let mut remote = sftp.create(&remote_path)?;
+    io::copy(&mut local, &mut remote)?;
+    Ok(Upload { url: self.produce_url(key), key: key.to_string() })
+  }
+
+  fn produce_url(&self, key: &str) -> String {
+    util::create_drop_url(self.config, key.to_string())
+  }
+
+  fn delete(&self, key: &str) -> Result<()> {
+    self.connect()?.unlink(&self.remote_path(key))?;
+    Ok(())
+  }
+
+  fn exists(&self, key: &str) -> Result<bool> {
+    Ok(self.connect()?.stat(&self.remote_path(key)).is_ok())
+  }
+}
+
+fn check_known_host(session: &Session, known_hosts_file: &Path, host: &str, port: u16, key: &[u8]) -> Result<()> {
+  let mut known_hosts = session.known_hosts()?;
+  if known_hosts_file.exists() {
+    known_hosts.read_file(known_hosts_file, KnownHostFileKind::OpenSSH)?;
+  }
+
+  match known_hosts.check_port(host, port, key) {
+    CheckResult::Match => Ok(()),
+    CheckResult::Mismatch => Err(anyhow!("Host key for SFTP server {} does not match known_hosts", host)),
+    _ => Err(anyhow!("SFTP server {} is not in known_hosts, connect with ssh once to add it", host)),
+  }
+}
+
+// Missing directories are created one level at a time, errors are ignored since most of the path
+// usually already exists and any real problem will surface when creating the file.
+fn ensure_remote_directory_exists(sftp: &Sftp, dir: &Path) {
+  if sftp.stat(dir).is_ok() {
+    return;
+  }
+  if let Some(parent) = dir.parent() {
+    ensure_remote_directory_exists(sftp, parent);
+  }
+  let _ = sftp.mkdir(dir, 0o755);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use hex;
+  use std::env;
+  use std::fs;
+
+  const SERVER_KEY: &'static str = "AAAAC3NzaC1lZDI1NTE5AAAAIIW5uv5qJycNiwTkbKqjQS9Tj+ampnl1QpN4a7cuZbvc";
+  const OTHER_KEY: &'static str = "AAAAC3NzaC1lZDI1NTE5AAAAIHGQ0DNA2rzvzxa/sD5aSRRV2hXsMooGiCJKwcnaCnD3";
+  // SERVER_KEY decoded
+  const SERVER_KEY_HEX: &'static str = "0000000b7373682d656432353531390000002085b9bafe6a27270d8b04e46caaa3412f538fe6a6a679754293786bb72e65bbdc";
+
+  fn config() -> DropConfig {
+    DropConfig::builder().dir(env::temp_dir().to_string_lossy().into_owned()).backend("sftp")
+      .sftp_host("files.example.com").sftp_remote_dir("/var/www/drop").host("https://drop.example.com/files")
+      .build().unwrap()
+  }
+
+  fn known_hosts(contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("drop-known-hosts-{}", util::rand_string(8)));
+    fs::write(&path, contents).unwrap();
+    path
+  }
+
+  fn check(known_hosts_file: &Path, port: u16) -> Result<()> {
+    let key = hex::decode(SERVER_KEY_HEX).unwrap();
+    check_known_host(&Session::new().unwrap(), known_hosts_file, "files.example.com", port, &key)
+  }
+
+  #[test]
+  fn builds_remote_paths_and_urls() {
+    let config = config();
+    let uploader = SftpUploader::new(&config).unwrap();
+    assert_eq!(uploader.remote_path("2024/06/shot.png"), PathBuf::from("/var/www/drop/2024/06/shot.png"));
+    assert_eq!(uploader.produce_url("2024/06/shot.png"), "https://drop.example.com/files/2024/06/shot.png");
+  }
+
+  #[test]
+  fn requires_a_host_for_urls() {
+    let config = DropConfig { host: None, ..config() };
+    assert!(SftpUploader::new(&config).is_err());
+  }
+
+  #[test]
+  fn accepts_known_host_keys() {
+    let file = known_hosts(&format!("files.example.com ssh-ed25519 {}\n[files.example.com]:2222 ssh-ed25519 {}\n", SERVER_KEY, SERVER_KEY));
+    assert!(check(&file, 22).is_ok());
+    assert!(check(&file, 2222).is_ok());
+    fs::remove_file(file).unwrap();
+  }
+
+  #[test]
+  fn rejects_unknown_hosts() {
+    let file = known_hosts(&format!("other.example.com ssh-ed25519 {}\n", SERVER_KEY));
+    assert!(check(&file, 22).unwrap_err().to_string().contains("is not in known_hosts"));
+    assert!(check(&env::temp_dir().join("drop-known-hosts-missing"), 22).unwrap_err().to_string().contains("is not in known_hosts"));
+    fs::remove_file(file).unwrap();
+  }
+
+  #[test]
+  fn rejects_changed_host_keys() {
+    let file = known_hosts(&format!("files.example.com ssh-ed25519 {}\n", OTHER_KEY));
+    assert!(check(&file, 22).unwrap_err().to_string().contains("does not match"));
+    fs::remove_file(file).unwrap();
+  }
+}
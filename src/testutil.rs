@@ -0,0 +1,121 @@
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+
+lazy_static! {
+  static ref ENV_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// Tests run in parallel within one process, so tests reading environment variables set them through
+// here. Variables given as None are removed, and all of them are restored afterwards.
+pub fn with_env<F: FnOnce()>(vars: &[(&str, Option<&str>)], f: F) {
+  let _lock = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+  let saved: Vec<(&str, Option<String>)> = vars.iter().map(|&(name, _)| (name, env::var(name).ok())).collect();
+  for &(name, value) in vars {
+    match value {
+      Some(value) => env::set_var(name, value),
+      None => env::remove_var(name),
+    }
+  }
+  let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(f));
+  for (name, value) in saved {
+    match value {
+      Some(value) => env::set_var(name, value),
+      None => env::remove_var(name),
+    }
+  }
+  if let Err(err) = result {
+    ::std::panic::resume_unwind(err);
+  }
+}
+
+// A request as received by the mock server, header names are lowercased.
+#[derive(Debug, Clone)]
+pub struct Request {
+  pub method: String,
+  pub path: String,
+  pub headers: Vec<(String, String)>,
+  pub body: Vec<u8>,
+}
+
+impl Request {
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self.headers.iter().find(|&&(ref k, _)| k == name).map(|&(_, ref v)| v.as_str())
+  }
+}
+
+// A canned response, written with Content-Length and Connection: close.
+pub struct Response {
+  pub status: u16,
+  pub headers: Vec<(String, String)>,
+  pub body: String,
+}
+
+impl Response {
+  pub fn new(status: u16, body: &str) -> Response {
+    Response { status: status, headers: Vec::new(), body: body.to_string() }
+  }
+
+  pub fn with_header(mut self, name: &str, value: &str) -> Response {
+    self.headers.push((name.to_string(), value.to_string()));
+    self
+  }
+}
+
+// Serves one connection per response in order on a local port, then returns the requests it received.
+pub fn serve(responses: Vec<Response>) -> (String, JoinHandle<Vec<Request>>) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let url = format!("http://{}", listener.local_addr().unwrap());
+  let handle = thread::spawn(move || {
+    let mut requests = Vec::new();
+    for response in responses {
+      let (stream, _) = listener.accept().unwrap();
+      let mut reader = BufReader::new(stream.try_clone().unwrap());
+      let request = read_request(&mut reader);
+      let is_head = request.method == "HEAD";
+      requests.push(request);
+
+      let mut stream = stream;
+      let mut head = format!("HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, response.body.len());
+      for (name, value) in response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+      }
+      head.push_str("\r\n");
+      stream.write_all(head.as_bytes()).unwrap();
+      if !is_head {
+        stream.write_all(response.body.as_bytes()).unwrap();
+      }
+    }
+    requests
+  });
+  (url, handle)
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> Request {
+  let mut line = String::new();
+  reader.read_line(&mut line).unwrap();
+  let mut parts = line.split_whitespace();
+  let method = parts.next().unwrap_or_default().to_string();
+  let path = parts.next().unwrap_or_default().to_string();
+
+  let mut headers = Vec::new();
+  loop {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let line = line.trim_end();
+    if line.is_empty() {
+      break;
+    }
+    if let Some(idx) = line.find(':') {
+      headers.push((line[..idx].trim().to_lowercase(), line[idx + 1..].trim().to_string()));
+    }
+  }
+
+  let length = headers.iter().find(|&&(ref k, _)| k == "content-length").and_then(|&(_, ref v)| v.parse::<usize>().ok()).unwrap_or(0);
+  let mut body = vec![0; length];
+  reader.read_exact(&mut body).unwrap();
+  Request { method: method, path: path, headers: headers, body: body }
+}
@@ -1,6 +1,8 @@
 use conf::DropConfig;
+use error::DropError;
 
 use std;
+use anyhow::Result;
 
 #[cfg(target_os = "linux")]
 use gtk;
@@ -20,10 +22,9 @@ use cocoa::foundation::{NSProcessInfo, NSAutoreleasePool, NSString};
 
 #[cfg(target_os = "linux")]
 #[allow(unused)]
-pub fn wait_for_user_stop(config: &DropConfig) {
+pub fn wait_for_user_stop(config: &DropConfig) -> Result<()> {
   if gtk::init().is_err() {
-    println!("Failed to initialize GTK.");
-    std::process::exit(1);
+    return Err(DropError::Capture("Failed to initialize GTK".to_string()).into());
   }
 
   let mut indicator = AppIndicator::new("Drop", "");
@@ -41,10 +42,11 @@ pub fn wait_for_user_stop(config: &DropConfig) {
   m.show_all();
 
   gtk::main();
+  Ok(())
 }
 
 #[cfg(target_os = "macos")]
-pub fn wait_for_user_stop() {
+pub fn wait_for_user_stop() -> Result<()> {
   unsafe {
     let _pool = NSAutoreleasePool::new(nil);
     let app = NSApp();
@@ -70,4 +72,5 @@ pub fn wait_for_user_stop() {
 
     app.run();
   }
+  Ok(())
 }
@@ -0,0 +1,187 @@
+use aws::S3Uploader;
+use conf::DropConfig;
+use error::DropError;
+use history;
+use mime;
+use naming;
+use report::DropReport;
+use http::HttpUploader;
+use sftp::SftpUploader;
+use sigv4;
+use util;
+
+use std::fs;
+use std::path::{Component, Path};
+use std::time::Instant;
+use anyhow::{Context, Result};
+
+#[derive(Debug, Clone)]
+pub struct Upload {
+  pub key: String,
+  pub url: String,
+}
+
+pub trait Uploader {
+  fn name(&self) -> &'static str;
+  fn upload(&self, file: &Path, key: &str) -> Result<Upload>;
+  fn produce_url(&self, key: &str) -> String;
+  fn delete(&self, key: &str) -> Result<()>;
+
+  // Backends that can't look up existing uploads always upload again.
+  fn exists(&self, _key: &str) -> Result<bool> {
+    Ok(false)
+  }
+}
+
+// Uploads a file with the configured backend under the given key and records it in the drop history.
+// With the hash strategy keys are derived from the content, so an existing object with the same key
+// already holds this file and the transfer is skipped. The existing object keeps its own ttl tag and
+// headers, so no expiry is recorded for it.
+pub fn upload_file(config: &DropConfig, file: &Path, key: &str, kind: &str) -> Result<DropReport> {
+  let uploader = create_uploader(config).context(DropError::Config("Invalid upload backend configuration".to_string()))?;
+  let started = Instant::now();
+  let existing = naming::is_content_addressed(config) && uploader.exists(key)
+    .context(DropError::Upload(format!("Failed to check for an existing upload of {}", key)))?;
+  let upload =
+    if existing {
+      eprintln!("{} has already been uploaded, skipping", key);
+      if config.ttl.is_some() {
+        eprintln!("WARNING: The ttl is not applied to the existing upload of {}", key);
+      }
+      Upload { url: uploader.produce_url(key), key: key.to_string() }
+    } else {
+      uploader.upload(file, key).context(DropError::Upload(format!("Failed to upload {}", util::path_to_str(file))))?
+    };
+  let mut report = DropReport::new(file, &upload, kind, uploader.name(), started.elapsed());
+  report.deduplicated = existing;
+  if let Err(err) = history::record(config, file, &upload, kind, uploader.name(), if existing { None } else { config.ttl }) {
+    eprintln!("WARNING: Failed to record drop in history: {}", err);
+  }
+  Ok(report)
+}
+
+pub fn create_uploader<'a>(config: &'a DropConfig) -> Result<Box<dyn Uploader + 'a>> {
+  match config.backend.as_ref() {
+    "s3" => Ok(Box::new(S3Uploader::new(config)?)),
+    "sftp" => Ok(Box::new(SftpUploader::new(config)?)),
+    "http" => Ok(Box::new(HttpUploader::new(config)?)),
+    "local" => Ok(Box::new(LocalUploader { dir: &config.dir })),
+    backend => Err(DropError::Config(format!("Unrecognized upload backend: {}", backend)).into()),
+  }
+}
+
+// Headers describing how the file should be served, for backends that can store them.
+pub fn content_headers(config: &DropConfig, file: &Path) -> Vec<(String, String)> {
+  let mut headers = vec![("Content-Type".to_string(), mime::detect_mime_type(file))];
+  if config.content_disposition == "attachment" {
+    let filename = file.file_name().map(|name| util::from_os_str(name)).unwrap_or_default();
+    headers.push(("Content-Disposition".to_string(), attachment_disposition(&filename)));
+  }
+  headers
+}
+
+fn attachment_disposition(filename: &str) -> String {
+  let ascii_name: String = filename.chars()
+    .map(|c| if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' })
+    .collect();
+  format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii_name, sigv4::uri_encode(filename, true))
+}
+
+// Keys are canonical paths, so they are compared with the canonical directory. The file itself may
+// already be gone.
+fn is_inside(dir: &str, key: &str) -> bool {
+  let path = Path::new(key);
+  !path.components().any(|c| c == Component::ParentDir)
+    && Path::new(dir).canonicalize().map(|dir| path.starts_with(dir)).unwrap_or(false)
+}
+
+// Leaves the file where it is and produces a file url pointing at it.
+pub struct LocalUploader<'a> {
+  dir: &'a str,
+}
+
+impl<'a> Uploader for LocalUploader<'a> {
+  fn name(&self) -> &'static str {
+    "local"
+  }
+
+  fn upload(&self, file: &Path, _key: &str) -> Result<Upload> {
+    let key = util::path_to_str(file.canonicalize()?.as_path());
+    Ok(Upload { url: self.produce_url(&key), key: key })
+  }
+
+  fn produce_url(&self, key: &str) -> String {
+    format!("file://{}", key)
+  }
+
+  fn delete(&self, key: &str) -> Result<()> {
+    if !is_inside(self.dir, key) {
+      return Err(anyhow!("Refusing to delete {}, it is outside the drop directory {}", key, self.dir));
+    }
+    fs::remove_file(key)?;
+    Ok(())
+  }
+}
+
+// Local drops are keyed by the path of the file itself, which only belongs to drop when it was written
+// to the drop directory, anything else is the user's own file.
+pub fn in_drop_dir(config: &DropConfig, key: &str) -> bool {
+  is_inside(&config.dir, key)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use error;
+  use std::env;
+
+  #[test]
+  fn creates_the_configured_uploader() {
+    let configs = vec![
+      ("local", DropConfig::builder().backend("local")),
+      ("s3", DropConfig::builder().backend("s3").aws_bucket("drops").aws_credentials("AKIDEXAMPLE", "secret")),
+      ("sftp", DropConfig::builder().backend("sftp").sftp_host("drop.example.com").host("drop.example.com")),
+      ("http", DropConfig::builder().backend("http").http_url("https://upload.example.com")),
+    ];
+    for (backend, builder) in configs {
+      let config = builder.build().unwrap();
+      assert_eq!(create_uploader(&config).unwrap().name(), backend);
+    }
+
+    assert!(create_uploader(&DropConfig::builder().backend("s3").build().unwrap()).is_err());
+    assert!(create_uploader(&DropConfig::builder().backend("sftp").host("example.com").build().unwrap()).is_err());
+    assert!(create_uploader(&DropConfig::builder().backend("http").build().unwrap()).is_err());
+
+    let mut config = DropConfig::builder().build().unwrap();
+    config.backend = "ftp".to_string();
+    let err = create_uploader(&config).err().unwrap();
+    assert_eq!(error::exit_code(&err), error::EXIT_CONFIG);
+    assert!(DropConfig::builder().backend("ftp").build().is_err());
+  }
+
+  #[test]
+  fn deletes_local_drops_only_inside_the_drop_directory() {
+    let root = env::temp_dir().join(format!("drop-upload-test-{}", util::rand_string(8)));
+    let dir = root.join("drops");
+    fs::create_dir_all(&dir).unwrap();
+    let inside = dir.join("shot.png");
+    let outside = root.join("original.png");
+    fs::write(&inside, "inside").unwrap();
+    fs::write(&outside, "outside").unwrap();
+
+    let config = DropConfig::builder().dir(util::path_to_str(&dir)).build().unwrap();
+    let uploader = create_uploader(&config).unwrap();
+    let outside_key = uploader.upload(&outside, "ignored").unwrap().key;
+    let inside_key = uploader.upload(&inside, "ignored").unwrap().key;
+    assert!(!in_drop_dir(&config, &outside_key));
+    assert!(in_drop_dir(&config, &inside_key));
+
+    assert!(uploader.delete(&outside_key).is_err());
+    assert!(uploader.delete(&format!("{}/../original.png", util::path_to_str(&dir))).is_err());
+    assert!(outside.exists());
+    uploader.delete(&inside_key).unwrap();
+    assert!(!inside.exists());
+
+    fs::remove_dir_all(root).unwrap();
+  }
+}
@@ -1,13 +1,28 @@
+use aws;
 use conf::DropConfig;
+use naming;
 
-use std::path::Path;
+use std::env;
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
+use std::fs::File;
 use std::{thread, time};
-use std::io::{BufReader, BufRead};
+use std::io;
+use std::io::{BufReader, BufRead, Read};
 use std::process::{Command, Child, Stdio, ExitStatus};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use rand;
 use rand::Rng;
 use rand::distributions::Alphanumeric;
+use sha2::{Digest, Sha256};
+use hex;
+use tar;
+
+const WORDS: &'static str = include_str!("words.txt");
+const BASE32_ALPHABET: &'static [u8] = b"0123456789abcdefghjkmnpqrstvwxyz";
 
 pub fn path_to_str(path: &Path) -> String {
   path.to_string_lossy().into_owned()
@@ -17,26 +32,126 @@ pub fn from_os_str(os_str: &OsStr) -> String {
   os_str.to_string_lossy().into_owned()
 }
 
+// Taken from $HOME rather than the deprecated std::env::home_dir.
+pub fn home_dir() -> Option<PathBuf> {
+  env::var_os("HOME").filter(|home| !home.is_empty()).map(PathBuf::from)
+}
+
+// Base directory from an XDG variable such as XDG_CONFIG_HOME, falling back to the given directory
+// under home. The spec says relative paths in these variables are to be ignored.
+pub fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+  env::var_os(var).map(PathBuf::from).filter(|dir| dir.is_absolute())
+    .or(home_dir().map(|home| home.join(fallback)))
+}
+
+// Only a leading ~ refers to the home directory, a tilde anywhere else is part of the name.
+pub fn expand_home_dir(path: &str) -> String {
+  match home_dir() {
+    Some(ref home) if path == "~" || path.starts_with("~/") => format!("{}{}", path_to_str(home), &path[1..]),
+    _ => path.to_string(),
+  }
+}
+
 pub fn wait_delay(config: &DropConfig) {
   if config.delay > 0 {
     thread::sleep(time::Duration::from_secs(config.delay));
   }
 }
 
+// Parses durations like 90s, 30m, 24h, 7d or 2w into seconds, a bare number is taken as seconds.
+pub fn parse_duration(value: &str) -> Option<u64> {
+  let value = value.trim();
+  let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+  let multiplier = match unit.trim() {
+    "" | "s" => 1,
+    "m" => 60,
+    "h" => 60 * 60,
+    "d" => 24 * 60 * 60,
+    "w" => 7 * 24 * 60 * 60,
+    _ => return None,
+  };
+  number.parse::<u64>().ok().map(|n| n * multiplier)
+}
+
+// Parses sizes like 512K, 100M or 2G into bytes, a bare number is taken as bytes.
+pub fn parse_size(value: &str) -> Option<u64> {
+  let value = value.trim();
+  let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+  let multiplier: u64 = match unit.trim().to_uppercase().trim_end_matches('B') {
+    "" => 1,
+    "K" => 1024,
+    "M" => 1024 * 1024,
+    "G" => 1024 * 1024 * 1024,
+    "T" => 1024 * 1024 * 1024 * 1024,
+    _ => return None,
+  };
+  number.parse::<u64>().ok().map(|n| n * multiplier)
+}
+
+// The inverse of parse_duration, using the largest unit the duration is a whole number of.
+pub fn format_duration(seconds: u64) -> String {
+  let units = [("w", 7 * 24 * 60 * 60), ("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)];
+  units.iter().find(|&&(_, size)| seconds > 0 && seconds % size == 0)
+    .map(|&(unit, size)| format!("{}{}", seconds / size, unit))
+    .unwrap_or(format!("{}s", seconds))
+}
+
+// The inverse of parse_size, using the largest unit the size is a whole number of.
+pub fn format_size(bytes: u64) -> String {
+  let units = [("T", 1024 * 1024 * 1024 * 1024), ("G", 1024 * 1024 * 1024), ("M", 1024 * 1024), ("K", 1024)];
+  units.iter().find(|&&(_, size)| bytes > 0 && bytes % size == 0)
+    .map(|&(unit, size)| format!("{}{}", bytes / size, unit))
+    .unwrap_or(bytes.to_string())
+}
+
+// Packs a directory into a .tar.gz next to it so it can be dropped as a single file.
+pub fn archive_directory(dir: &Path) -> Result<PathBuf> {
+  let archive_path = dir.with_extension("tar.gz");
+  let archive_file = File::create(&archive_path).with_context(|| format!("Failed to create archive {:?}", archive_path))?;
+  let enc = GzEncoder::new(archive_file, Compression::default());
+  let mut tar = tar::Builder::new(enc);
+  let file_name = dir.file_name().map(|s| from_os_str(s)).unwrap_or("archive".to_string());
+  tar.append_dir_all(file_name, dir).and_then(|_| tar.finish())
+    .with_context(|| format!("Failed to archive directory {:?}", dir))?;
+  Ok(archive_path)
+}
+
+// A url template takes precedence over the host, which may carry its own scheme and path prefix.
 pub fn create_drop_url(config: &DropConfig, filename: String) -> String {
-  match config.host.clone() {
-    Some(host) => format!("http://{}/{}", host, filename),
-    None => format!("http://s3.amazonaws.com/{}/{}", config.aws_bucket.clone().unwrap(), filename)
+  match (config.url_template.clone(), config.host.clone()) {
+    (Some(template), _) => expand_url_template(&template, config, &filename, &Local::now()),
+    (None, Some(host)) => format!("{}/{}", with_scheme(host.trim_end_matches('/'), "http"), filename),
+    (None, None) => aws::object_url(config, &filename)
+  }
+}
+
+// Fills in {key}, {filename}, {host}, {bucket} and the {yyyy}, {mm}, {dd} date of the drop. The date isn't
+// part of the key, so the expanded url is kept in the history rather than produced again later.
+fn expand_url_template(template: &str, config: &DropConfig, key: &str, date: &DateTime<Local>) -> String {
+  template
+    .replace("{key}", key)
+    .replace("{filename}", key.rsplit('/').next().unwrap_or(key))
+    .replace("{host}", config.host.as_ref().map(|h| h.as_str()).unwrap_or(""))
+    .replace("{bucket}", config.aws_bucket.as_ref().map(|b| b.as_str()).unwrap_or(""))
+    .replace("{yyyy}", &date.format("%Y").to_string())
+    .replace("{mm}", &date.format("%m").to_string())
+    .replace("{dd}", &date.format("%d").to_string())
+}
+
+pub fn with_scheme(endpoint: &str, default_scheme: &str) -> String {
+  if endpoint.contains("://") {
+    endpoint.to_string()
+  } else {
+    format!("{}://{}", default_scheme, endpoint)
   }
 }
 
 pub fn generate_filename(config: &DropConfig, recommended_filename: Option<String>, recommended_ext: Option<String>) -> String {
   let file_base = generate_filename_base(config, recommended_filename.clone());
-  let file_ext = generate_filename_extension(config, recommended_filename, recommended_ext);
-  if file_ext.is_some() && !file_ext.clone().unwrap().is_empty() {
-    format!("{}.{}", file_base, file_ext.unwrap())
-  } else {
-    file_base
+  let file_ext = generate_filename_extension(config, recommended_filename, recommended_ext).map(|ext| naming::sanitize(&ext));
+  match file_ext {
+    Some(ref ext) if !ext.is_empty() => format!("{}.{}", file_base, ext),
+    _ => file_base,
   }
 }
 
@@ -46,17 +161,17 @@ fn generate_filename_base(config: &DropConfig, recommended_filename: Option<Stri
   } else if recommended_filename.is_some() {
     create_filename_base_from_existing(config, recommended_filename.unwrap())
   } else {
-    rand_string(config.unique_length)
+    unique_string(config, None)
   }
 }
 
 fn create_filename_base_from_existing(config: &DropConfig, filename: String) -> String {
-  let file_base = filename.splitn(2, '.').next().unwrap();
+  let file_base = naming::sanitize(&naming::split_filename(&filename).0);
   match config.filename_strategy.as_ref() {
-    "exact" => file_base.to_string(),
-    "append" => append_rand_string(file_base, config.unique_length),
-    "replace" => rand_string(config.unique_length),
-    _ => prepend_rand_string(file_base, config.unique_length),
+    "exact" => file_base,
+    "append" => append_unique_string(&file_base, config),
+    "replace" | "hash" => unique_string(config, None),
+    _ => prepend_unique_string(&file_base, config),
   }
 }
 
@@ -64,39 +179,75 @@ fn generate_filename_extension(config: &DropConfig, recommended_file_name: Optio
   if config.extension.is_some() {
     config.extension.clone()
   } else if config.filename.is_some() {
-    config.filename.clone().unwrap().splitn(2, '.').nth(1).map(|s| s.to_string()).or(recommended_ext)
+    naming::split_filename(&config.filename.clone().unwrap()).1.or(recommended_ext)
   } else if recommended_ext.is_some() {
     recommended_ext
   } else if recommended_file_name.is_some() {
-    recommended_file_name.unwrap().splitn(2, '.').nth(1).or(Some("")).map(|s| s.to_string())
+    naming::split_filename(&recommended_file_name.unwrap()).1.or(Some(String::new()))
   } else {
     None
   }
 }
 
-fn prepend_rand_string(value: &str, len: usize) -> String {
-  format!("{}--{}", rand_string(len), value)
+fn prepend_unique_string(value: &str, config: &DropConfig) -> String {
+  format!("{}--{}", unique_string(config, None), value)
+}
+
+fn append_unique_string(value: &str, config: &DropConfig) -> String {
+  format!("{}--{}", value, unique_string(config, None))
+}
+
+// Unique part of generated filenames in the configured style. The length is a number of words for
+// the words style and of characters otherwise, defaulting to unique_words or unique_length.
+pub fn unique_string(config: &DropConfig, len: Option<usize>) -> String {
+  match config.unique_style.as_ref() {
+    "words" => rand_words(len.unwrap_or(config.unique_words)),
+    "base32" => rand_base32(len.unwrap_or(config.unique_length)),
+    _ => rand_string(len.unwrap_or(config.unique_length)),
+  }
+}
+
+fn rand_words(count: usize) -> String {
+  let words: Vec<&str> = WORDS.lines().collect();
+  let mut rng = rand::thread_rng();
+  (0..count).map(|_| words[rng.gen_range(0..words.len())]).collect::<Vec<&str>>().join("-")
 }
 
-fn append_rand_string(value: &str, len: usize) -> String {
-  format!("{}--{}", value, rand_string(len))
+// Crockford's alphabet, which leaves out i, l, o and u so ids can't be misread or spell words.
+fn rand_base32(len: usize) -> String {
+  let mut rng = rand::thread_rng();
+  (0..len).map(|_| BASE32_ALPHABET[rng.gen_range(0..BASE32_ALPHABET.len())] as char).collect()
+}
+
+pub fn sha256_file(file_path: &Path) -> io::Result<String> {
+  let mut file = File::open(file_path)?;
+  let mut hasher = Sha256::new();
+  let mut buffer = [0; 64 * 1024];
+  loop {
+    let read = file.read(&mut buffer)?;
+    if read == 0 {
+      break;
+    }
+    hasher.update(&buffer[..read]);
+  }
+  Ok(hex::encode(hasher.finalize()))
 }
 
 pub fn rand_string(len: usize) -> String {
   rand::thread_rng().sample_iter(Alphanumeric).take(len).map(char::from).collect()
 }
 
-pub fn run_command_and_wait(cmd: &mut Command, name: &str, config: &DropConfig) -> ExitStatus {
-  run_command(cmd, name, config).wait().unwrap()
+pub fn run_command_and_wait(cmd: &mut Command, name: &str, config: &DropConfig) -> io::Result<ExitStatus> {
+  run_command(cmd, name, config)?.wait()
 }
 
-pub fn run_command(cmd: &mut Command, name: &str, config: &DropConfig) -> Child {
+pub fn run_command(cmd: &mut Command, name: &str, config: &DropConfig) -> io::Result<Child> {
   if config.verbose {
-    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
     log_child_output_to_stdout(&mut child, name);
-    child
+    Ok(child)
   } else {
-    cmd.stdout(Stdio::null()).stderr(Stdio::null()).spawn().unwrap()
+    cmd.stdout(Stdio::null()).stderr(Stdio::null()).spawn()
   }
 }
 
@@ -108,19 +259,85 @@ fn log_child_output_to_stdout(child: &mut Child, name: &str) {
   
   thread::spawn(move || {
     if let Some(ref mut stdout) = stdout {
-      for line in BufReader::new(stdout).lines() {
-        let line = line.unwrap();
-        println!("[{}] {}", name_out, line);
+      for line in BufReader::new(stdout).lines().filter_map(|line| line.ok()) {
+        eprintln!("[{}] {}", name_out, line);
       }
     }
   });
 
   thread::spawn(move || {
     if let Some(ref mut stderr) = stderr {
-      for line in BufReader::new(stderr).lines() {
-        let line = line.unwrap();
-        println!("[{}] {}", name_err, line);
+      for line in BufReader::new(stderr).lines().filter_map(|line| line.ok()) {
+        eprintln!("[{}] {}", name_err, line);
       }
     }
   });
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testutil;
+  use chrono::TimeZone;
+
+  #[test]
+  fn expands_url_templates() {
+    let config = DropConfig::builder().host("example.com").aws_bucket("drops")
+      .url_template("https://cdn.example.com/{bucket}/{key}?via={host}&name={filename}").build().unwrap();
+    assert_eq!(create_drop_url(&config, "2024/05/shot.png".to_string()),
+               "https://cdn.example.com/drops/2024/05/shot.png?via=example.com&name=shot.png");
+  }
+
+  #[test]
+  fn generates_unique_words() {
+    let config = DropConfig::builder().unique_style("words").unique_words(3).build().unwrap();
+    let words: Vec<&str> = WORDS.lines().collect();
+    for _ in 0..20 {
+      let unique = unique_string(&config, None);
+      let parts: Vec<&str> = unique.split('-').collect();
+      assert_eq!(parts.len(), 3, "{}", unique);
+      assert!(parts.iter().all(|part| words.contains(part)), "{}", unique);
+    }
+    assert_eq!(unique_string(&config, Some(5)).split('-').count(), 5);
+  }
+
+  #[test]
+  fn generates_unambiguous_base32() {
+    let config = DropConfig::builder().unique_style("base32").unique_length(12).build().unwrap();
+    for _ in 0..20 {
+      let unique = unique_string(&config, None);
+      assert_eq!(unique.len(), 12);
+      assert!(unique.chars().all(|c| BASE32_ALPHABET.contains(&(c as u8))), "{}", unique);
+      assert!(!unique.contains(|c| "ilou".contains(c)), "{}", unique);
+    }
+    assert_eq!(unique_string(&config, Some(4)).len(), 4);
+  }
+
+  #[test]
+  fn expands_only_a_leading_tilde() {
+    let home = path_to_str(&home_dir().unwrap());
+    assert_eq!(expand_home_dir("~"), home);
+    assert_eq!(expand_home_dir("~/drops"), format!("{}/drops", home));
+    assert_eq!(expand_home_dir("/mnt/~backup/drops"), "/mnt/~backup/drops");
+    assert_eq!(expand_home_dir("/mnt/backup/~/drops"), "/mnt/backup/~/drops");
+    assert_eq!(expand_home_dir("~other/drops"), "~other/drops");
+  }
+
+  // Other tests read these variables, so they're only pointed at harmless locations.
+  #[test]
+  fn ignores_relative_xdg_directories() {
+    let data_dir = path_to_str(&env::temp_dir());
+    testutil::with_env(&[("XDG_DATA_HOME", Some(&data_dir)), ("XDG_CACHE_HOME", Some("cache"))], || {
+      assert_eq!(xdg_dir("XDG_DATA_HOME", ".local/share"), Some(env::temp_dir()));
+      assert_eq!(xdg_dir("XDG_CACHE_HOME", ".cache"), Some(home_dir().unwrap().join(".cache")));
+    });
+  }
+
+  #[test]
+  fn expands_dates_in_url_templates() {
+    let config = DropConfig::builder().url_template("https://cdn.example.com/{yyyy}/{mm}/{dd}/{key}").build().unwrap();
+    let date = Local.with_ymd_and_hms(2024, 5, 7, 23, 59, 0).unwrap();
+    assert_eq!(expand_url_template(config.url_template.as_ref().unwrap(), &config, "shot.png", &date),
+               "https://cdn.example.com/2024/05/07/shot.png");
+  }
+}